@@ -1,7 +1,32 @@
+use clap::builder::{PossibleValuesParser, TypedValueParser};
 use clap::{Arg, ArgAction, Command};
+use universal_deploy::format::ConfigFormat;
+use universal_deploy::overrides::Override;
+use universal_deploy::version::Bump;
+
+/// Names of subcommands that don't take the top-level config argument
+const SUBCOMMANDS: &[&str] = &[
+    "completion",
+    "config",
+    "resources",
+    "schema",
+    "secrets",
+    "validate",
+];
 
 /// Builds the CLI command structure
 pub fn build_command() -> Command {
+    base_command(true)
+}
+
+/// Builds a CLI command for subcommand invocations
+/// This variant doesn't require the top-level config argument
+pub fn build_command_for_subcommand() -> Command {
+    base_command(false)
+}
+
+/// Builds the shared command definition
+fn base_command(config_required: bool) -> Command {
     Command::new("universal-deploy")
         .version(env!("CARGO_PKG_VERSION"))
         .about("A tool to run deployment based on configuration files")
@@ -15,17 +40,163 @@ pub fn build_command() -> Command {
                         .help("Shell to generate completions for"),
                 ),
         )
+        .subcommand(
+            Command::new("config")
+                .about("Work with configuration files")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("convert")
+                        .about("Translate a configuration file between YAML, TOML and JSON")
+                        .arg(
+                            Arg::new("config")
+                                .value_name("FILE")
+                                .help("Path to the configuration file")
+                                .required(true),
+                        )
+                        .arg(format_arg(
+                            "from",
+                            "Format of the input (defaults to its extension)",
+                        ))
+                        .arg(format_arg(
+                            "to",
+                            "Format to write (defaults to the extension of --output)",
+                        ))
+                        .arg(output_arg("Output path (defaults to stdout)")),
+                )
+                .subcommand(
+                    Command::new("show")
+                        .about(
+                            "Print a configuration with its extended and included files merged in",
+                        )
+                        .arg(
+                            Arg::new("config")
+                                .value_name("FILE")
+                                .help("Path to the configuration file")
+                                .required(true),
+                        )
+                        .arg(
+                            Arg::new("resolved")
+                                .long("resolved")
+                                .help("Annotate every value with the file and line it came from")
+                                .action(ArgAction::SetTrue),
+                        )
+                        .arg(format_arg(
+                            "format",
+                            "Format of the configuration file (defaults to its extension)",
+                        ))
+                        .arg(set_arg()),
+                ),
+        )
+        .subcommand(
+            Command::new("resources")
+                .about("Manage resource files")
+                .subcommand_required(true)
+                .subcommand(
+                    Command::new("lock")
+                        .about("Write a lockfile with the SHA-256 of every resource")
+                        .arg(
+                            Arg::new("config")
+                                .value_name("FILE")
+                                .help("Path to the configuration file")
+                                .required(true),
+                        ),
+                ),
+        )
+        .subcommand(
+            Command::new("schema")
+                .about("Print the JSON Schema of the configuration format")
+                .arg(output_arg("Output path (defaults to stdout)")),
+        )
+        .subcommand(
+            Command::new("secrets")
+                .about("Encrypt, decrypt and edit encrypted resource files")
+                .subcommand_required(true)
+                .subcommand(
+                    secrets_subcommand("encrypt", "Encrypt a file")
+                        .arg(output_arg("Output path (defaults to <FILE>.enc)")),
+                )
+                .subcommand(
+                    secrets_subcommand("decrypt", "Decrypt a file")
+                        .arg(output_arg("Output path (defaults to stdout)")),
+                )
+                .subcommand(secrets_subcommand(
+                    "edit",
+                    "Edit an encrypted file in $EDITOR, creating it if missing",
+                )),
+        )
+        .subcommand(
+            Command::new("validate")
+                .about("Check a configuration file and report every problem found")
+                .arg(
+                    Arg::new("config")
+                        .value_name("FILE")
+                        .help("Path to the configuration file")
+                        .required(true),
+                )
+                .arg(
+                    Arg::new("remote")
+                        .long("remote")
+                        .help("Also check that the branch or tag exists on the remote repository")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(format_arg(
+                    "format",
+                    "Format of the configuration file (defaults to its extension)",
+                ))
+                .arg(set_arg())
+                .arg(
+                    Arg::new("lenient")
+                        .long("lenient")
+                        .help("Report unknown configuration keys as warnings instead of errors")
+                        .action(ArgAction::SetTrue),
+                )
+                .arg(
+                    Arg::new("override-policy")
+                        .long("override-policy")
+                        .help("Allow --set to change policy keys such as `allowed_branches`")
+                        .action(ArgAction::SetTrue),
+                ),
+        )
         .arg(
             Arg::new("config")
                 .value_name("FILE")
-                .help("Path to the configuration file")
-                .required(true),
+                .help("Path to the configuration file, `-` for stdin or git+<url>#<ref>:<path>")
+                .required(config_required),
+        )
+        .arg(
+            Arg::new("releases")
+                .value_name("RELEASE")
+                .num_args(0..)
+                .help("Names of the releases to run, for files defining `releases`"),
+        )
+        .arg(
+            Arg::new("all")
+                .long("all")
+                .help("Run every release defined under `releases`")
+                .conflicts_with("releases")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("parallel")
+                .long("parallel")
+                .help("Run independent releases at the same time (requires `clean: true`)")
+                .conflicts_with("jobs")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("jobs")
+                .short('j')
+                .long("jobs")
+                .value_name("N")
+                .value_parser(clap::value_parser!(u16).range(1..))
+                .help("Run up to N independent releases at the same time (requires `clean: true` when N > 1)"),
         )
         .arg(
             Arg::new("verbose")
                 .short('v')
                 .long("verbose")
                 .help("Enable verbose output")
+                .global(true)
                 .action(ArgAction::SetTrue),
         )
         .arg(
@@ -34,50 +205,121 @@ pub fn build_command() -> Command {
                 .help("Keep the checkout directory after deployment (only applies to clean mode)")
                 .action(ArgAction::SetTrue),
         )
-}
-
-/// Builds a CLI command for the completion subcommand
-/// This variant doesn't require the config argument
-pub fn build_command_for_completion() -> Command {
-    Command::new("universal-deploy")
-        .version(env!("CARGO_PKG_VERSION"))
-        .about("A tool to run deployment based on configuration files")
-        .subcommand(
-            Command::new("completion")
-                .about("Generate shell completion scripts")
-                .arg(
-                    Arg::new("shell")
-                        .required(true)
-                        .value_parser(clap::value_parser!(clap_complete::Shell))
-                        .help("Shell to generate completions for"),
-                ),
+        .arg(
+            Arg::new("env")
+                .short('e')
+                .long("env")
+                .value_name("NAME")
+                .help("Environment to deploy to, available to `when:` conditions as `env`"),
         )
         .arg(
-            Arg::new("config")
-                .value_name("FILE")
-                .help("Path to the configuration file")
-                .required(false), // Not required for completion generation
+            Arg::new("pass-exit-code")
+                .long("pass-exit-code")
+                .help("Exit with the tool's own exit code when it fails")
+                .action(ArgAction::SetTrue),
         )
+        .arg(format_arg(
+            "format",
+            "Format of the configuration file (defaults to its extension)",
+        ))
         .arg(
-            Arg::new("verbose")
-                .short('v')
-                .long("verbose")
-                .help("Enable verbose output")
+            Arg::new("lenient")
+                .long("lenient")
+                .help("Report unknown configuration keys as warnings instead of errors")
                 .action(ArgAction::SetTrue),
         )
+        .arg(set_arg())
         .arg(
-            Arg::new("keep-checkout")
-                .long("keep-checkout")
-                .help("Keep the checkout directory after deployment (only applies to clean mode)")
+            Arg::new("yes")
+                .short('y')
+                .long("yes")
+                .help("Deploy without asking for confirmation, e.g. in CI")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("override-policy")
+                .long("override-policy")
+                .help("Deploy even if the branch breaks `allowed_branches` or `require_tag_pattern`, and allow --set to change policy keys; recorded in the history")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("bump")
+                .long("bump")
+                .value_name("PART")
+                .value_parser(
+                    PossibleValuesParser::new(Bump::NAMES)
+                        .map(|name| name.parse::<Bump>().expect("Bump name is valid")),
+                )
+                .help("Version component to increase for releases with `version` (inferred from commits by default)"),
+        )
+        .arg(
+            Arg::new("show-diff-stat")
+                .long("show-diff-stat")
+                .help("Also print `git diff --stat` of the changes being deployed")
+                .action(ArgAction::SetTrue),
+        )
+        .arg(
+            Arg::new("dry-run")
+                .long("dry-run")
+                .help("Show the deployment plan without checking out or running anything")
                 .action(ArgAction::SetTrue),
         )
 }
 
-/// Checks if the first command-line argument is "completion"
-pub fn is_completion_command() -> bool {
+/// Builds a `secrets` subcommand operating on a single file
+fn secrets_subcommand(name: &'static str, about: &'static str) -> Command {
+    Command::new(name)
+        .about(about)
+        .arg(
+            Arg::new("file")
+                .value_name("FILE")
+                .help("File to operate on")
+                .required(true),
+        )
+        .arg(
+            Arg::new("key-file")
+                .long("key-file")
+                .value_name("PATH")
+                .help("File containing the hex-encoded key (defaults to $UD_SECRETS_KEY or $UD_SECRETS_KEY_FILE)"),
+        )
+}
+
+/// Builds the `--output` argument for subcommands writing a file
+fn output_arg(help: &'static str) -> Arg {
+    Arg::new("output")
+        .short('o')
+        .long("output")
+        .value_name("PATH")
+        .help(help)
+}
+
+/// Builds an argument selecting a configuration file format
+fn format_arg(name: &'static str, help: &'static str) -> Arg {
+    Arg::new(name)
+        .long(name)
+        .value_name("FORMAT")
+        .value_parser(
+            PossibleValuesParser::new(ConfigFormat::NAMES)
+                .map(|name| name.parse::<ConfigFormat>().expect("Format name is valid")),
+        )
+        .help(help)
+}
+
+/// Builds the repeatable `--set PATH=VALUE` argument overriding configuration values
+fn set_arg() -> Arg {
+    Arg::new("set")
+        .long("set")
+        .value_name("PATH=VALUE")
+        .value_parser(|value: &str| value.parse::<Override>())
+        .action(ArgAction::Append)
+        .help("Override a configuration value, e.g. release.branch=hotfix or tool.arguments[1]=staging")
+}
+
+/// Checks if the first command-line argument is a subcommand
+pub fn is_subcommand_invocation() -> bool {
     std::env::args()
         .nth(1)
-        .map(|arg| arg == "completion")
+        .map(|arg| SUBCOMMANDS.contains(&arg.as_str()))
         .unwrap_or(false)
 }
 
@@ -0,0 +1,307 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::error::DeployError;
+
+/// Values a `when:` expression is evaluated against
+#[derive(Debug, Clone, Copy)]
+pub struct Context<'a> {
+    /// Environment selected with `--env`, if any
+    pub env: Option<&'a str>,
+    /// Branch being deployed
+    pub branch: &'a str,
+    /// Release variables, available as `vars.NAME`
+    pub variables: &'a BTreeMap<String, String>,
+    /// Repository checkout that `exists()` paths are relative to
+    pub repo_path: Option<&'a Path>,
+}
+
+/// Evaluates a `when:` expression
+///
+/// Supported syntax: string literals in double quotes, `true`/`false`,
+/// the identifiers `env`, `branch` and `vars.NAME`, `exists("path")`,
+/// the operators `==`, `!=`, `!`, `&&`, `||` and parentheses.
+///
+/// # Example
+/// `env == "production" && exists("migrations")`
+pub fn evaluate(expression: &str, context: &Context) -> Result<bool, DeployError> {
+    let ast = parse(expression)?;
+    let value = ast.eval(context).map_err(|e| {
+        DeployError::Condition(format!(
+            "Could not evaluate condition '{}': {}",
+            expression, e
+        ))
+    })?;
+
+    match value {
+        Value::Bool(result) => Ok(result),
+        Value::Str(_) => Err(DeployError::Condition(format!(
+            "Condition '{}' evaluates to a string, not a boolean",
+            expression
+        ))),
+    }
+}
+
+/// Checks the syntax of a `when:` expression without evaluating it
+pub fn check(expression: &str) -> Result<(), DeployError> {
+    parse(expression).map(|_| ())
+}
+
+/// Parses an expression into its syntax tree
+fn parse(expression: &str) -> Result<Expr, DeployError> {
+    let invalid =
+        |e: String| DeployError::Condition(format!("Invalid condition '{}': {}", expression, e));
+
+    let tokens = tokenize(expression).map_err(invalid)?;
+    let mut parser = Parser { tokens, pos: 0 };
+
+    parser
+        .parse_or()
+        .and_then(|expr| match parser.peek() {
+            None => Ok(expr),
+            Some(token) => Err(format!("unexpected {:?}", token)),
+        })
+        .map_err(invalid)
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+    Str(String),
+    Ident(String),
+    Eq,
+    NotEq,
+    Not,
+    And,
+    Or,
+    LParen,
+    RParen,
+}
+
+/// Splits an expression into tokens
+fn tokenize(input: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = Vec::new();
+    let mut chars = input.chars().peekable();
+
+    while let Some(&c) = chars.peek() {
+        match c {
+            c if c.is_whitespace() => {
+                chars.next();
+            }
+            '"' => {
+                chars.next();
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('"') => break,
+                        Some('\\') => match chars.next() {
+                            Some(escaped) => value.push(escaped),
+                            None => return Err("unterminated string".to_string()),
+                        },
+                        Some(c) => value.push(c),
+                        None => return Err("unterminated string".to_string()),
+                    }
+                }
+                tokens.push(Token::Str(value));
+            }
+            '(' => {
+                chars.next();
+                tokens.push(Token::LParen);
+            }
+            ')' => {
+                chars.next();
+                tokens.push(Token::RParen);
+            }
+            '=' | '!' | '&' | '|' => {
+                chars.next();
+                let next = chars.peek().copied();
+                let token = match (c, next) {
+                    ('=', Some('=')) => Token::Eq,
+                    ('!', Some('=')) => Token::NotEq,
+                    ('&', Some('&')) => Token::And,
+                    ('|', Some('|')) => Token::Or,
+                    ('!', _) => {
+                        tokens.push(Token::Not);
+                        continue;
+                    }
+                    _ => return Err(format!("unexpected '{}'", c)),
+                };
+                chars.next();
+                tokens.push(token);
+            }
+            c if c.is_alphanumeric() || c == '_' => {
+                let mut ident = String::new();
+                while let Some(&c) = chars.peek() {
+                    if c.is_alphanumeric() || c == '_' || c == '.' || c == '-' {
+                        ident.push(c);
+                        chars.next();
+                    } else {
+                        break;
+                    }
+                }
+                tokens.push(Token::Ident(ident));
+            }
+            _ => return Err(format!("unexpected '{}'", c)),
+        }
+    }
+
+    Ok(tokens)
+}
+
+#[derive(Debug)]
+enum Expr {
+    Literal(Value),
+    Ident(String),
+    Exists(String),
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+    Eq(Box<Expr>, Box<Expr>),
+    NotEq(Box<Expr>, Box<Expr>),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Value {
+    Str(String),
+    Bool(bool),
+}
+
+struct Parser {
+    tokens: Vec<Token>,
+    pos: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.pos)
+    }
+
+    fn next(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.pos).cloned();
+        self.pos += 1;
+        token
+    }
+
+    fn expect(&mut self, expected: Token) -> Result<(), String> {
+        match self.next() {
+            Some(token) if token == expected => Ok(()),
+            Some(token) => Err(format!("expected {:?}, found {:?}", expected, token)),
+            None => Err(format!("expected {:?}, found end of expression", expected)),
+        }
+    }
+
+    fn parse_or(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_and()?;
+        while self.peek() == Some(&Token::Or) {
+            self.next();
+            left = Expr::Or(Box::new(left), Box::new(self.parse_and()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_and(&mut self) -> Result<Expr, String> {
+        let mut left = self.parse_unary()?;
+        while self.peek() == Some(&Token::And) {
+            self.next();
+            left = Expr::And(Box::new(left), Box::new(self.parse_unary()?));
+        }
+        Ok(left)
+    }
+
+    fn parse_unary(&mut self) -> Result<Expr, String> {
+        if self.peek() == Some(&Token::Not) {
+            self.next();
+            return Ok(Expr::Not(Box::new(self.parse_unary()?)));
+        }
+        self.parse_comparison()
+    }
+
+    fn parse_comparison(&mut self) -> Result<Expr, String> {
+        let left = self.parse_primary()?;
+        match self.peek() {
+            Some(Token::Eq) => {
+                self.next();
+                Ok(Expr::Eq(Box::new(left), Box::new(self.parse_primary()?)))
+            }
+            Some(Token::NotEq) => {
+                self.next();
+                Ok(Expr::NotEq(Box::new(left), Box::new(self.parse_primary()?)))
+            }
+            _ => Ok(left),
+        }
+    }
+
+    fn parse_primary(&mut self) -> Result<Expr, String> {
+        match self.next() {
+            Some(Token::LParen) => {
+                let expr = self.parse_or()?;
+                self.expect(Token::RParen)?;
+                Ok(expr)
+            }
+            Some(Token::Str(value)) => Ok(Expr::Literal(Value::Str(value))),
+            Some(Token::Ident(name)) => match name.as_str() {
+                "true" => Ok(Expr::Literal(Value::Bool(true))),
+                "false" => Ok(Expr::Literal(Value::Bool(false))),
+                "exists" => {
+                    self.expect(Token::LParen)?;
+                    let path = match self.next() {
+                        Some(Token::Str(path)) => path,
+                        _ => return Err("exists() takes a quoted path".to_string()),
+                    };
+                    self.expect(Token::RParen)?;
+                    Ok(Expr::Exists(path))
+                }
+                "env" | "branch" => Ok(Expr::Ident(name)),
+                _ if name.starts_with("vars.") && name.len() > "vars.".len() => {
+                    Ok(Expr::Ident(name))
+                }
+                _ => Err(format!("unknown identifier '{}'", name)),
+            },
+            Some(token) => Err(format!("unexpected {:?}", token)),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+impl Expr {
+    fn eval(&self, context: &Context) -> Result<Value, String> {
+        match self {
+            Expr::Literal(value) => Ok(value.clone()),
+            Expr::Ident(name) => match name.as_str() {
+                "env" => Ok(Value::Str(context.env.unwrap_or_default().to_string())),
+                "branch" => Ok(Value::Str(context.branch.to_string())),
+                _ => {
+                    let var = &name["vars.".len()..];
+                    context
+                        .variables
+                        .get(var)
+                        .map(|value| Value::Str(value.clone()))
+                        .ok_or_else(|| format!("undefined variable '{}'", var))
+                }
+            },
+            Expr::Exists(path) => {
+                let repo_path = context
+                    .repo_path
+                    .ok_or("exists() can only be evaluated against a checkout")?;
+                Ok(Value::Bool(repo_path.join(path).exists()))
+            }
+            Expr::Not(inner) => Ok(Value::Bool(!inner.eval_bool(context)?)),
+            Expr::And(left, right) => Ok(Value::Bool(
+                left.eval_bool(context)? && right.eval_bool(context)?,
+            )),
+            Expr::Or(left, right) => Ok(Value::Bool(
+                left.eval_bool(context)? || right.eval_bool(context)?,
+            )),
+            Expr::Eq(left, right) => Ok(Value::Bool(left.eval(context)? == right.eval(context)?)),
+            Expr::NotEq(left, right) => {
+                Ok(Value::Bool(left.eval(context)? != right.eval(context)?))
+            }
+        }
+    }
+
+    fn eval_bool(&self, context: &Context) -> Result<bool, String> {
+        match self.eval(context)? {
+            Value::Bool(value) => Ok(value),
+            Value::Str(value) => Err(format!("expected a boolean, found string \"{}\"", value)),
+        }
+    }
+}
@@ -1,15 +1,45 @@
-use serde::{Deserialize, Serialize};
-use std::fs;
-use std::path::Path;
+use indexmap::IndexMap;
+use schemars::JsonSchema;
+use serde::de::{self, IgnoredAny, MapAccess, Visitor};
+use serde::{Deserialize, Deserializer, Serialize};
+use std::collections::BTreeMap;
+
+use crate::condition;
+use crate::error::DeployError;
+use crate::format::ConfigFormat;
+use crate::overrides::{self, Override};
+use crate::resolve::{self, Resolved};
+use crate::template;
+use crate::version::Version;
 
 /// Top-level configuration structure
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Config {
+    /// Configuration file this one is based on, relative to this file
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub extends: Option<String>,
+
+    /// Configuration files merged into this one in order, relative to this file
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub include: Vec<String>,
+
+    /// Release settings, for files that define a single release
+    #[serde(default)]
     pub release: ReleaseConfig,
+
+    /// Named releases, for files that deploy several services
+    #[serde(default, skip_serializing_if = "IndexMap::is_empty")]
+    pub releases: IndexMap<String, ReleaseConfig>,
+
+    /// Values set on the command line with `--set`
+    #[serde(skip)]
+    pub overrides: Vec<Override>,
 }
 
 /// Release configuration settings
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct ReleaseConfig {
     /// Whether to create a clean checkout in a new directory
     #[serde(default)]
@@ -23,32 +53,158 @@ pub struct ReleaseConfig {
     #[serde(default)]
     pub branch: String,
 
-    /// Whether to merge after deployment (not yet implemented)
+    /// Glob patterns `branch` must match, e.g. `release/*`; any branch if empty
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_branches: Vec<String>,
+
+    /// Glob pattern of the tag `branch` must name, e.g. `v*`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub require_tag_pattern: Option<String>,
+
+    /// Whether to fetch and merge the latest upstream changes into an existing checkout before deploying
     #[serde(default)]
     pub merge: bool,
 
+    /// Signature `HEAD` must carry before resources are copied or the tool runs
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub verify_signature: Option<SignatureCheck>,
+
+    /// Fingerprints of the GPG or SSH keys trusted by `verify_signature`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub allowed_signers: Vec<String>,
+
+    /// Whether to push the branch and the created tag to `origin` once the tool succeeds
+    #[serde(default)]
+    pub push: bool,
+
+    /// Whether `push` may overwrite the branch, as long as it hasn't moved since the checkout
+    #[serde(default)]
+    pub force_with_lease: bool,
+
+    /// Branches the deployed branch is merged into and pushed once the tool succeeds
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub merge_back: Vec<String>,
+
+    /// Directory or directories containing resource files, searched in order
+    #[serde(default)]
+    pub resources_dir: ResourcesDir,
+
     /// List of resource files to copy
     #[serde(default)]
     pub resources: Vec<Resource>,
 
+    /// What to do with copied resources after the tool runs (non-clean mode only)
+    #[serde(default)]
+    pub resources_cleanup: ResourceCleanup,
+
     /// Tool to use for deployment
     #[serde(default)]
     pub tool: ToolConfig,
 
-    /// Whether to create a git tag (not yet implemented)
+    /// Whether to create an annotated tag on the checkout's `HEAD` once the tool succeeds
+    ///
+    /// That is the deployed commit, or the changelog commit made on top of it
+    /// with `release_notes.changelog`.
     #[serde(default)]
     pub tag: bool,
+
+    /// Name of the tag created with `tag: true`, see [`TAG_PLACEHOLDERS`]
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tag_name: Option<String>,
+
+    /// Release notes generated from the deployed commits
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release_notes: Option<ReleaseNotesConfig>,
+
+    /// Semantic versioning from `vX.Y.Z` tags
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub version: Option<VersionConfig>,
+
+    /// Variables available to `when:` conditions as `vars.NAME`
+    #[serde(default)]
+    pub variables: BTreeMap<String, String>,
+
+    /// Names of releases under `releases` that must succeed before this one runs
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub depends_on: Vec<String>,
+
+    /// Whether to show the plan and ask for confirmation before deploying
+    #[serde(default)]
+    pub confirm: bool,
+
+    /// Environments that always ask for confirmation, e.g. `production`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub protected_environments: Vec<String>,
+}
+
+/// Placeholders available in `tag_name`
+pub const TAG_PLACEHOLDERS: &[&str] = &["branch", "date", "env", "timestamp", "version"];
+
+/// Tag name used when `tag_name` isn't set and the release has no `version`
+pub const DEFAULT_TAG_NAME: &str = "deploy-${timestamp}";
+
+/// How the version of a release is determined
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct VersionConfig {
+    /// Prefix of version tags
+    #[serde(default = "default_tag_prefix")]
+    pub tag_prefix: String,
+
+    /// Version used while no version tag exists, as `MAJOR.MINOR.PATCH`
+    #[serde(default = "default_initial_version")]
+    pub initial: String,
+}
+
+impl Default for VersionConfig {
+    fn default() -> Self {
+        VersionConfig {
+            tag_prefix: default_tag_prefix(),
+            initial: default_initial_version(),
+        }
+    }
+}
+
+fn default_tag_prefix() -> String {
+    "v".to_string()
+}
+
+fn default_initial_version() -> String {
+    "0.1.0".to_string()
+}
+
+/// Where release notes for the deployed commits go
+#[derive(Debug, Default, Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
+pub struct ReleaseNotesConfig {
+    /// File to write the notes to, relative to the current directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub file: Option<String>,
+
+    /// Whether to use the notes as the message of the tag created with `tag: true`
+    #[serde(default)]
+    pub tag_message: bool,
+
+    /// Whether to prepend the notes to `CHANGELOG.md` in the checkout and commit it
+    #[serde(default)]
+    pub changelog: bool,
 }
 
 /// Tool configuration for deployment
-#[derive(Debug, Deserialize, Serialize, Clone)]
+#[derive(Debug, Serialize, Clone, JsonSchema)]
 #[serde(untagged)]
 pub enum ToolConfig {
     /// Full configuration with command and arguments
+    #[schemars(extend("additionalProperties" = false))]
     Full {
+        /// Command to execute
         command: String,
+        /// Arguments passed to the command
         #[serde(default)]
         arguments: Vec<String>,
+        /// Condition under which the tool runs
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        when: Option<String>,
     },
     /// Simple string for command without arguments
     Simple(String),
@@ -60,7 +216,59 @@ impl Default for ToolConfig {
     }
 }
 
+// Deserialized by hand rather than as an untagged enum, so that unknown keys in
+// the full form are reported like everywhere else instead of failing to match
+impl<'de> Deserialize<'de> for ToolConfig {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct ToolVisitor;
+
+        impl<'de> Visitor<'de> for ToolVisitor {
+            type Value = ToolConfig;
+
+            fn expecting(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+                f.write_str("a command string or a map with `command`, `arguments` and `when`")
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<ToolConfig, E> {
+                Ok(ToolConfig::Simple(value.to_string()))
+            }
+
+            fn visit_unit<E: de::Error>(self) -> Result<ToolConfig, E> {
+                Ok(ToolConfig::default())
+            }
+
+            fn visit_map<A: MapAccess<'de>>(self, mut map: A) -> Result<ToolConfig, A::Error> {
+                let mut command = None;
+                let mut arguments = None;
+                let mut when = None;
+
+                while let Some(key) = map.next_key::<String>()? {
+                    match key.as_str() {
+                        "command" => command = Some(map.next_value()?),
+                        "arguments" => arguments = Some(map.next_value()?),
+                        "when" => when = map.next_value()?,
+                        _ => {
+                            map.next_value::<IgnoredAny>()?;
+                        }
+                    }
+                }
+
+                Ok(ToolConfig::Full {
+                    command: command.ok_or_else(|| de::Error::missing_field("command"))?,
+                    arguments: arguments.unwrap_or_default(),
+                    when,
+                })
+            }
+        }
+
+        deserializer.deserialize_any(ToolVisitor)
+    }
+}
+
 impl ToolConfig {
+    /// Keys accepted by the full form
+    const FIELDS: &'static [&'static str] = &["command", "arguments", "when"];
+
     /// Returns the command name, if any
     pub fn command(&self) -> Option<&str> {
         match self {
@@ -79,6 +287,14 @@ impl ToolConfig {
         }
     }
 
+    /// Returns the `when:` condition, if any
+    pub fn condition(&self) -> Option<&str> {
+        match self {
+            ToolConfig::Full { when, .. } => when.as_deref(),
+            _ => None,
+        }
+    }
+
     /// Checks if tool is configured
     pub fn is_empty(&self) -> bool {
         self.command().is_none()
@@ -89,7 +305,9 @@ impl std::fmt::Display for ToolConfig {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         match self {
             ToolConfig::Simple(cmd) => write!(f, "{}", cmd),
-            ToolConfig::Full { command, arguments } => {
+            ToolConfig::Full {
+                command, arguments, ..
+            } => {
                 write!(f, "{}", command)?;
                 if !arguments.is_empty() {
                     write!(f, " {}", arguments.join(" "))?;
@@ -100,50 +318,535 @@ impl std::fmt::Display for ToolConfig {
     }
 }
 
+/// Resource directories, relative to the configuration file
+#[derive(Debug, Deserialize, Serialize, Clone, JsonSchema)]
+#[serde(untagged)]
+pub enum ResourcesDir {
+    /// A single directory
+    Single(String),
+    /// Several directories searched in order, e.g. environment-specific then shared
+    Multiple(Vec<String>),
+}
+
+impl Default for ResourcesDir {
+    fn default() -> Self {
+        ResourcesDir::Single("resources".to_string())
+    }
+}
+
+impl ResourcesDir {
+    /// Returns the directories in search order
+    pub fn dirs(&self) -> Vec<&str> {
+        match self {
+            ResourcesDir::Single(dir) => vec![dir.as_str()],
+            ResourcesDir::Multiple(dirs) => dirs.iter().map(String::as_str).collect(),
+        }
+    }
+}
+
+impl std::fmt::Display for ResourcesDir {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}", self.dirs().join(", "))
+    }
+}
+
 /// Resource file to copy into the deployment
-#[derive(Debug, Deserialize, Serialize)]
+#[derive(Debug, Clone, Deserialize, Serialize, JsonSchema)]
+#[schemars(deny_unknown_fields)]
 pub struct Resource {
-    /// Source file name (relative to resources directory)
+    /// Source file name (relative to a resources directory)
     pub file: String,
 
     /// Optional destination path (defaults to same as file)
     #[serde(rename = "copy")]
     pub copy_path: Option<String>,
+
+    /// Optional expected SHA-256 of the source file, verified before copying
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub sha256: Option<String>,
+
+    /// Whether the source file is encrypted with `ud secrets encrypt`
+    #[serde(default)]
+    pub encrypted: bool,
+
+    /// Condition under which the resource is copied, e.g. `env == "production"`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub when: Option<String>,
+
+    /// Whether the resource may replace a file that already exists
+    #[serde(default)]
+    pub overwrite: OverwritePolicy,
+}
+
+/// Policy for resources whose destination already exists
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum OverwritePolicy {
+    /// Never replace an existing file
+    Never,
+    /// Replace existing files unless they are tracked in git
+    #[default]
+    Untracked,
+    /// Always replace existing files
+    Always,
+}
+
+/// What carries the signature checked before deploying
+#[derive(Debug, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureCheck {
+    /// The deployed commit itself is signed
+    Commit,
+    /// A signed tag points to the deployed commit
+    Tag,
+}
+
+impl std::fmt::Display for SignatureCheck {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            SignatureCheck::Commit => write!(f, "commit"),
+            SignatureCheck::Tag => write!(f, "tag"),
+        }
+    }
+}
+
+/// Handling of copied resources once the tool has run
+#[derive(Debug, Default, Deserialize, Serialize, Clone, Copy, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "lowercase")]
+pub enum ResourceCleanup {
+    /// Leave copied resources in place
+    #[default]
+    Keep,
+    /// Delete files created by `ud`; replaced files are still put back, never deleted
+    Remove,
+    /// Put back replaced files and delete new ones
+    Restore,
+}
+
+impl std::fmt::Display for ResourceCleanup {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceCleanup::Keep => write!(f, "keep"),
+            ResourceCleanup::Remove => write!(f, "remove"),
+            ResourceCleanup::Restore => write!(f, "restore"),
+        }
+    }
+}
+
+impl std::fmt::Display for OverwritePolicy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            OverwritePolicy::Never => write!(f, "never"),
+            OverwritePolicy::Untracked => write!(f, "untracked"),
+            OverwritePolicy::Always => write!(f, "always"),
+        }
+    }
+}
+
+impl Resource {
+    /// Returns the destination path relative to the repository
+    ///
+    /// Without an explicit `copy` path, encrypted resources drop their
+    /// `.enc` extension so `secrets.env.enc` is written as `secrets.env`.
+    pub fn destination(&self) -> &str {
+        match &self.copy_path {
+            Some(copy) => copy,
+            None if self.encrypted => self
+                .file
+                .strip_suffix(crate::secrets::ENCRYPTED_EXTENSION)
+                .unwrap_or(&self.file),
+            None => &self.file,
+        }
+    }
+}
+
+/// A problem found in a configuration
+#[derive(Debug, Clone)]
+pub struct ConfigProblem {
+    /// Dotted path of the offending key, e.g. `release.resources.0.file`
+    pub path: String,
+    /// Description of the problem
+    pub message: String,
+}
+
+impl ConfigProblem {
+    /// Creates a problem for the key at `path`
+    pub fn new(path: impl Into<String>, message: impl Into<String>) -> Self {
+        ConfigProblem {
+            path: path.into(),
+            message: message.into(),
+        }
+    }
+}
+
+/// Options controlling how a configuration file is loaded
+#[derive(Debug, Default, Clone)]
+pub struct LoadOptions {
+    /// File format, detected from the extension if not set
+    pub format: Option<ConfigFormat>,
+    /// Print unknown keys as warnings instead of failing
+    pub lenient: bool,
+    /// Values set on the command line, applied after merging includes
+    pub overrides: Vec<Override>,
+    /// Allow overrides of policy keys such as `allowed_branches`
+    pub override_policy: bool,
 }
 
 impl Config {
-    /// Loads configuration from a YAML file
+    /// Loads configuration from a file, rejecting unknown keys
     ///
     /// # Arguments
-    /// * `path` - Path to the configuration YAML file
+    /// * `path` - Path to the configuration file (YAML, TOML or JSON)
     ///
     /// # Returns
     /// Parsed configuration or error
-    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
-        if !Path::new(path).exists() {
-            return Err(format!("Config file does not exist: {}", path).into());
+    pub fn load(path: &str) -> Result<Self, DeployError> {
+        Config::load_with(path, &LoadOptions::default())
+    }
+
+    /// Loads configuration from a file
+    ///
+    /// # Arguments
+    /// * `path` - Path to the configuration file (YAML, TOML or JSON)
+    /// * `options` - Options controlling how the file is loaded
+    ///
+    /// # Returns
+    /// Parsed configuration or error
+    pub fn load_with(path: &str, options: &LoadOptions) -> Result<Self, DeployError> {
+        let mut resolved = resolve::resolve(path, options.format)?;
+        overrides::apply(&mut resolved, &options.overrides, options.override_policy)?;
+
+        let (mut config, unknown_keys) = Config::from_resolved(&resolved)?;
+        config.overrides = options.overrides.clone();
+        if unknown_keys.is_empty() {
+            return Ok(config);
         }
 
-        let contents = fs::read_to_string(path)
-            .map_err(|e| format!("Could not read config file: {}", e))?;
+        let messages: Vec<String> = unknown_keys
+            .iter()
+            .map(|problem| locate(&resolved, &problem.path, &problem.message))
+            .collect();
 
-        let config: Config = serde_yaml::from_str(&contents)
-            .map_err(|e| format!("Failed to parse YAML config file: {}", e))?;
+        if !options.lenient {
+            return Err(DeployError::ParseError {
+                path: path.to_string(),
+                message: messages.join("; "),
+            });
+        }
 
+        for message in messages {
+            eprintln!("Warning: {}: {}", path, message);
+        }
         Ok(config)
     }
 
+    /// Builds the configuration from a resolved configuration file
+    ///
+    /// # Arguments
+    /// * `resolved` - Configuration with its `extends` and `include` files merged in
+    ///
+    /// # Returns
+    /// Parsed configuration and a problem for every key that was not recognized
+    pub fn from_resolved(resolved: &Resolved) -> Result<(Self, Vec<ConfigProblem>), DeployError> {
+        let mut unknown_keys = Vec::new();
+        let mut record_unknown = |key: serde_ignored::Path| unknown_keys.push(key_path(&key));
+        let deserializer =
+            serde_ignored::Deserializer::new(resolved.value.clone(), &mut record_unknown);
+
+        let config: Config = serde_path_to_error::deserialize(deserializer).map_err(|e| {
+            let key = error_path(e.path());
+            // Without a position, `locate` already names the key
+            let message = match resolved.origin(&key).and_then(|o| o.position) {
+                Some(_) => format!("{}: {}", key, e.inner()),
+                None => e.inner().to_string(),
+            };
+            DeployError::ParseError {
+                path: resolved.path.clone(),
+                message: locate(resolved, &key, &message),
+            }
+        })?;
+
+        let problems = unknown_keys
+            .into_iter()
+            .map(|key| {
+                let (parent, name) = key.rsplit_once('.').unwrap_or(("", &key));
+                let message = match suggest_key(parent, name) {
+                    Some(known) => format!("Unknown key '{}' (did you mean '{}'?)", name, known),
+                    None => format!("Unknown key '{}'", name),
+                };
+                ConfigProblem::new(key.clone(), message)
+            })
+            .collect();
+
+        Ok((config, problems))
+    }
+
+    /// Returns the JSON Schema of the configuration format
+    pub fn json_schema() -> schemars::Schema {
+        schemars::schema_for!(Config)
+    }
+
+    /// Validates the configuration, including its branch policies
+    pub fn validate(&self) -> Result<(), DeployError> {
+        self.validate_with(false).map(|_| ())
+    }
+
     /// Validates the configuration
-    pub fn validate(&self) -> Result<(), Box<dyn std::error::Error>> {
-        if self.release.repository.is_empty() {
-            return Err("Repository URL cannot be empty".into());
+    ///
+    /// # Arguments
+    /// * `override_policy` - Whether to only warn about branch policy violations
+    ///
+    /// # Returns
+    /// Whether a branch policy violation was overridden
+    pub fn validate_with(&self, override_policy: bool) -> Result<bool, DeployError> {
+        if let Some(problem) = self.structure_problems().into_iter().next() {
+            return Err(DeployError::ValidationError(problem.message));
         }
 
-        if self.release.branch.is_empty() {
-            return Err("Branch name cannot be empty".into());
+        let mut overridden = false;
+        for problem in self.policy_problems() {
+            if !override_policy {
+                return Err(DeployError::ValidationError(format!(
+                    "{} (pass --override-policy to deploy anyway)",
+                    problem.message
+                )));
+            }
+            eprintln!("Warning: Overriding policy: {}", problem.message);
+            overridden = true;
+        }
+        Ok(overridden)
+    }
+
+    /// Returns every validation problem of the configuration
+    pub fn problems(&self) -> Vec<ConfigProblem> {
+        let mut problems = self.structure_problems();
+        problems.extend(self.policy_problems());
+        problems
+    }
+
+    /// Returns a problem for every release whose branch breaks `allowed_branches` or `require_tag_pattern`
+    ///
+    /// Overrides are applied before this is checked, so `--set branch=...`
+    /// is held to the same policies as the file.
+    pub fn policy_problems(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+
+        for (key, release) in self.release_entries() {
+            let mut message = None;
+            if !release.allowed_branches.is_empty()
+                && !release
+                    .allowed_branches
+                    .iter()
+                    .any(|pattern| glob_match(pattern, &release.branch))
+            {
+                message = Some(format!(
+                    "Branch '{}' isn't allowed (allowed_branches: {})",
+                    release.branch,
+                    release.allowed_branches.join(", ")
+                ));
+            } else if let Some(pattern) = &release.require_tag_pattern {
+                if !glob_match(pattern, &release.branch) {
+                    message = Some(format!(
+                        "'{}' doesn't match the required tag pattern '{}'",
+                        release.branch, pattern
+                    ));
+                }
+            }
+
+            if let Some(mut message) = message {
+                if let Some(name) = key.strip_prefix("releases.") {
+                    message = format!("Release '{}': {}", name, message);
+                }
+                problems.push(ConfigProblem::new(format!("{}.branch", key), message));
+            }
         }
 
-        Ok(())
+        problems
+    }
+
+    /// Returns the problems with the configuration's structure and values, other than policies
+    fn structure_problems(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+
+        // An unset `release` is all defaults
+        let has_release = serde_yaml::to_value(&self.release).ok()
+            != serde_yaml::to_value(ReleaseConfig::default()).ok();
+        if !self.releases.is_empty() && has_release {
+            problems.push(ConfigProblem::new(
+                "release",
+                "Define either `release` or `releases`, not both",
+            ));
+        }
+
+        for (key, release) in self.release_entries() {
+            problems.extend(release_problems(&key, release));
+        }
+
+        if !self.release.depends_on.is_empty() {
+            problems.push(ConfigProblem::new(
+                "release.depends_on",
+                "`depends_on` only applies to releases under `releases`",
+            ));
+        }
+        problems.extend(self.dependency_problems());
+
+        problems
+    }
+
+    /// Checks that dependencies name known releases and don't form a cycle
+    fn dependency_problems(&self) -> Vec<ConfigProblem> {
+        let mut problems = Vec::new();
+
+        for (name, release) in &self.releases {
+            for (i, dependency) in release.depends_on.iter().enumerate() {
+                if self.releases.contains_key(dependency) {
+                    continue;
+                }
+                let mut message =
+                    format!("Release '{}': Unknown dependency '{}'", name, dependency);
+                if let Some(suggestion) =
+                    closest(dependency, self.releases.keys().map(String::as_str))
+                {
+                    message.push_str(&format!(", did you mean '{}'?", suggestion));
+                }
+                problems.push(ConfigProblem::new(
+                    format!("releases.{}.depends_on.{}", name, i),
+                    message,
+                ));
+            }
+        }
+
+        if let Some(cycle) = self.dependency_cycle() {
+            problems.push(ConfigProblem::new(
+                format!("releases.{}.depends_on", cycle[0]),
+                format!(
+                    "Releases depend on each other in a cycle: {}",
+                    cycle.join(" -> ")
+                ),
+            ));
+        }
+
+        problems
+    }
+
+    /// Returns the names along a dependency cycle, starting and ending with the same release
+    fn dependency_cycle(&self) -> Option<Vec<&str>> {
+        // Releases on the current path, and releases known to be free of cycles
+        fn visit<'a>(
+            releases: &'a IndexMap<String, ReleaseConfig>,
+            name: &'a str,
+            path: &mut Vec<&'a str>,
+            done: &mut Vec<&'a str>,
+        ) -> Option<Vec<&'a str>> {
+            if let Some(start) = path.iter().position(|&n| n == name) {
+                let mut cycle = path[start..].to_vec();
+                cycle.push(name);
+                return Some(cycle);
+            }
+            if done.contains(&name) {
+                return None;
+            }
+
+            path.push(name);
+            let dependencies = releases.get(name).map(|r| r.depends_on.as_slice());
+            for dependency in dependencies.unwrap_or_default() {
+                if let Some(cycle) = visit(releases, dependency, path, done) {
+                    return Some(cycle);
+                }
+            }
+            path.pop();
+            done.push(name);
+            None
+        }
+
+        let mut done = Vec::new();
+        self.releases
+            .keys()
+            .find_map(|name| visit(&self.releases, name, &mut Vec::new(), &mut done))
+    }
+
+    /// Orders the named releases so that each comes after the ones it depends on
+    ///
+    /// Releases keep the order they are defined in where dependencies allow.
+    /// Only dependencies among `names` are considered; the others are assumed
+    /// to be deployed already.
+    ///
+    /// # Arguments
+    /// * `names` - Releases to run, all of them keys of `releases`
+    pub fn release_order(&self, names: &[String]) -> Vec<String> {
+        fn visit(
+            config: &Config,
+            names: &[String],
+            name: &str,
+            seen: &mut Vec<String>,
+            order: &mut Vec<String>,
+        ) {
+            if seen.iter().any(|n| n == name) || !names.iter().any(|n| n == name) {
+                return;
+            }
+            seen.push(name.to_string());
+            if let Some(release) = config.releases.get(name) {
+                for dependency in &release.depends_on {
+                    visit(config, names, dependency, seen, order);
+                }
+            }
+            order.push(name.to_string());
+        }
+
+        let mut seen = Vec::new();
+        let mut order = Vec::new();
+        for name in self.releases.keys() {
+            visit(self, names, name, &mut seen, &mut order);
+        }
+        order
+    }
+
+    /// Returns the key path and settings of every release in the file
+    ///
+    /// This is `release` for single-release files, and each entry of
+    /// `releases` otherwise.
+    pub fn release_entries(&self) -> Vec<(String, &ReleaseConfig)> {
+        if self.releases.is_empty() {
+            return vec![("release".to_string(), &self.release)];
+        }
+        self.releases
+            .iter()
+            .map(|(name, release)| (format!("releases.{}", name), release))
+            .collect()
+    }
+
+    /// Returns the names of the releases under `releases`
+    pub fn release_names(&self) -> Vec<&str> {
+        self.releases.keys().map(String::as_str).collect()
+    }
+
+    /// Returns a configuration for one of the named releases
+    ///
+    /// # Arguments
+    /// * `name` - Key of the release under `releases`
+    ///
+    /// # Returns
+    /// A single-release configuration, or an error naming the releases available
+    pub fn release_named(&self, name: &str) -> Result<Config, DeployError> {
+        let Some(release) = self.releases.get(name) else {
+            let mut message = format!("Unknown release '{}'", name);
+            if let Some(suggestion) = closest(name, self.releases.keys().map(String::as_str)) {
+                message.push_str(&format!(", did you mean '{}'?", suggestion));
+            }
+            message.push_str(&format!(
+                " (available: {})",
+                self.release_names().join(", ")
+            ));
+            return Err(DeployError::ValidationError(message));
+        };
+
+        Ok(Config {
+            extends: None,
+            include: Vec::new(),
+            release: release.clone(),
+            releases: IndexMap::new(),
+            overrides: self.overrides.clone(),
+        })
     }
 
     /// Prints configuration summary to stdout
@@ -152,19 +855,467 @@ impl Config {
             println!("Successfully parsed config: {:#?}", self);
         } else {
             println!("Release configuration:");
+            if !self.overrides.is_empty() {
+                println!("  Overrides:");
+                for item in &self.overrides {
+                    println!("    {}", item);
+                }
+            }
             println!("  Clean: {}", self.release.clean);
             println!("  Repository: {}", self.release.repository);
             println!("  Branch: {}", self.release.branch);
+            if !self.release.allowed_branches.is_empty() {
+                println!(
+                    "  Allowed branches: {}",
+                    self.release.allowed_branches.join(", ")
+                );
+            }
+            if let Some(pattern) = &self.release.require_tag_pattern {
+                println!("  Required tag pattern: {}", pattern);
+            }
             println!("  Merge: {}", self.release.merge);
-            println!("  Tool: {}", self.release.tool);
+            if let Some(check) = self.release.verify_signature {
+                println!("  Verify signature: {}", check);
+                for signer in &self.release.allowed_signers {
+                    println!("    allowed='{}'", signer);
+                }
+            }
+            if self.release.push {
+                if self.release.force_with_lease {
+                    println!("  Push: true (force with lease)");
+                } else {
+                    println!("  Push: true");
+                }
+            }
+            if !self.release.merge_back.is_empty() {
+                println!("  Merge back into: {}", self.release.merge_back.join(", "));
+            }
+            if self.release.tool.is_empty() {
+                println!("  Tool: (none)");
+            } else {
+                println!("  Tool: {}", self.release.tool);
+            }
+            if let Some(when) = self.release.tool.condition() {
+                println!("    when='{}'", when);
+            }
             println!("  Tag: {}", self.release.tag);
+            if let Some(tag_name) = &self.release.tag_name {
+                println!("    name='{}'", tag_name);
+            }
+            if let Some(version) = &self.release.version {
+                println!(
+                    "  Versioning: {}X.Y.Z tags (initially {})",
+                    version.tag_prefix, version.initial
+                );
+            }
+            if let Some(notes) = &self.release.release_notes {
+                println!("  Release notes:");
+                if let Some(file) = &notes.file {
+                    println!("    file='{}'", file);
+                }
+                if notes.tag_message {
+                    println!("    tag_message=true");
+                }
+                if notes.changelog {
+                    println!("    changelog=true");
+                }
+            }
+            if !self.release.depends_on.is_empty() {
+                println!("  Depends on: {}", self.release.depends_on.join(", "));
+            }
+            if self.release.confirm {
+                println!("  Confirm: true");
+            }
+            if !self.release.protected_environments.is_empty() {
+                println!(
+                    "  Protected environments: {}",
+                    self.release.protected_environments.join(", ")
+                );
+            }
+            if !self.release.variables.is_empty() {
+                println!("  Variables:");
+                for (name, value) in &self.release.variables {
+                    println!("    {}='{}'", name, value);
+                }
+            }
+            println!("  Resources directory: {}", self.release.resources_dir);
             println!("  Resources: {} items", self.release.resources.len());
+            if !self.release.clean {
+                println!("  Resources cleanup: {}", self.release.resources_cleanup);
+            }
             for (i, resource) in self.release.resources.iter().enumerate() {
                 println!("    [{}]: file='{}'", i, resource.file);
                 if let Some(copy) = &resource.copy_path {
                     println!("         copy='{}'", copy);
                 }
+                if let Some(sha256) = &resource.sha256 {
+                    println!("         sha256='{}'", sha256);
+                }
+                if resource.encrypted {
+                    println!("         encrypted=true");
+                }
+                if let Some(when) = &resource.when {
+                    println!("         when='{}'", when);
+                }
+                if resource.overwrite != OverwritePolicy::default() {
+                    println!("         overwrite={}", resource.overwrite);
+                }
+            }
+        }
+    }
+}
+
+/// Prefixes a message about the key at `path` with where that key was defined
+fn locate(resolved: &Resolved, path: &str, message: &str) -> String {
+    let origin = resolved.origin(path);
+    let file = origin
+        .map(|origin| origin.file.as_str())
+        .filter(|file| *file != resolved.path);
+
+    match (file, origin.and_then(|origin| origin.position)) {
+        (None, Some((line, column))) => format!("line {}, column {}: {}", line, column, message),
+        (Some(file), Some((line, column))) => {
+            format!("{}, line {}, column {}: {}", file, line, column, message)
+        }
+        (None, None) => format!("{}: {}", path, message),
+        (Some(file), None) => format!("{}: {}: {}", file, path, message),
+    }
+}
+
+/// Formats the path of a deserialization error as a dotted key path
+fn error_path(path: &serde_path_to_error::Path) -> String {
+    let segments: Vec<String> = path
+        .iter()
+        .filter_map(|segment| match segment {
+            serde_path_to_error::Segment::Seq { index } => Some(index.to_string()),
+            serde_path_to_error::Segment::Map { key } => Some(key.clone()),
+            serde_path_to_error::Segment::Enum { variant } => Some(variant.clone()),
+            serde_path_to_error::Segment::Unknown => None,
+        })
+        .collect();
+    segments.join(".")
+}
+
+/// Returns the validation problems of one release
+///
+/// # Arguments
+/// * `key` - Key path of the release, e.g. `release` or `releases.api`
+/// * `release` - Release settings to check
+fn release_problems(key: &str, release: &ReleaseConfig) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+
+    if release.repository.is_empty() {
+        problems.push(ConfigProblem::new(
+            format!("{}.repository", key),
+            "Repository URL cannot be empty",
+        ));
+    }
+
+    if release.branch.is_empty() {
+        problems.push(ConfigProblem::new(
+            format!("{}.branch", key),
+            "Branch name cannot be empty",
+        ));
+    }
+
+    if release.resources_dir.dirs().is_empty() {
+        problems.push(ConfigProblem::new(
+            format!("{}.resources_dir", key),
+            "Resources directory list cannot be empty",
+        ));
+    }
+
+    for (i, resource) in release.resources.iter().enumerate() {
+        if let Some(when) = &resource.when {
+            if let Err(e) = condition::check(when) {
+                problems.push(ConfigProblem::new(
+                    format!("{}.resources.{}.when", key, i),
+                    e.to_string(),
+                ));
             }
         }
     }
+
+    if let Some(tag_name) = &release.tag_name {
+        // `${version}` only has a value for releases with `version`
+        let placeholders: Vec<&str> = TAG_PLACEHOLDERS
+            .iter()
+            .copied()
+            .filter(|&name| name != "version" || release.version.is_some())
+            .collect();
+        if let Err(message) = template::check(tag_name, &placeholders) {
+            problems.push(ConfigProblem::new(format!("{}.tag_name", key), message));
+        }
+    }
+
+    if let Some(version) = &release.version {
+        if let Err(message) = version.initial.parse::<Version>() {
+            problems.push(ConfigProblem::new(
+                format!("{}.version.initial", key),
+                message,
+            ));
+        }
+    }
+
+    if release
+        .release_notes
+        .as_ref()
+        .is_some_and(|notes| notes.tag_message)
+        && !release.tag
+    {
+        problems.push(ConfigProblem::new(
+            format!("{}.release_notes.tag_message", key),
+            "`tag_message` needs `tag: true`",
+        ));
+    }
+
+    // A clean checkout is removed afterwards, taking unpushed tags and commits with it
+    if release.clean && !release.push {
+        if release.tag {
+            problems.push(ConfigProblem::new(
+                format!("{}.tag", key),
+                "`tag` in a clean checkout needs `push: true`, or the tag is removed with the checkout",
+            ));
+        }
+        if release
+            .release_notes
+            .as_ref()
+            .is_some_and(|notes| notes.changelog)
+        {
+            problems.push(ConfigProblem::new(
+                format!("{}.release_notes.changelog", key),
+                "`changelog` in a clean checkout needs `push: true`, or the commit is removed with the checkout",
+            ));
+        }
+    }
+
+    if release.verify_signature.is_some() && release.allowed_signers.is_empty() {
+        problems.push(ConfigProblem::new(
+            format!("{}.verify_signature", key),
+            "`verify_signature` needs at least one fingerprint in `allowed_signers`",
+        ));
+    }
+    if release.verify_signature.is_none() && !release.allowed_signers.is_empty() {
+        problems.push(ConfigProblem::new(
+            format!("{}.allowed_signers", key),
+            "`allowed_signers` has no effect without `verify_signature`",
+        ));
+    }
+
+    if release.force_with_lease && !release.push {
+        problems.push(ConfigProblem::new(
+            format!("{}.force_with_lease", key),
+            "`force_with_lease` needs `push: true`",
+        ));
+    }
+
+    for (i, target) in release.merge_back.iter().enumerate() {
+        if target.trim().is_empty() {
+            problems.push(ConfigProblem::new(
+                format!("{}.merge_back.{}", key, i),
+                "Branch name is empty",
+            ));
+        } else if *target == release.branch {
+            problems.push(ConfigProblem::new(
+                format!("{}.merge_back.{}", key, i),
+                format!("Can't merge branch '{}' back into itself", target),
+            ));
+        }
+    }
+
+    if let Some(when) = release.tool.condition() {
+        if let Err(e) = condition::check(when) {
+            problems.push(ConfigProblem::new(
+                format!("{}.tool.when", key),
+                e.to_string(),
+            ));
+        }
+    }
+
+    // Messages of named releases say which release they are about
+    if let Some(name) = key.strip_prefix("releases.") {
+        for problem in &mut problems {
+            problem.message = format!("Release '{}': {}", name, problem.message);
+        }
+    }
+
+    problems
+}
+
+/// Returns the known key closest to an unknown `name` under `parent`, if any is close enough
+fn suggest_key(parent: &str, name: &str) -> Option<&'static str> {
+    let mut segments: Vec<&str> = parent.split('.').collect();
+    // Named releases accept the same keys as `release`
+    if segments.len() >= 2 && segments[0] == "releases" {
+        segments.splice(0..2, ["release"]);
+    }
+    let known: &[&str] = match segments.as_slice() {
+        [""] => struct_fields::<Config>(),
+        ["release"] => struct_fields::<ReleaseConfig>(),
+        ["release", "tool"] => ToolConfig::FIELDS,
+        ["release", "release_notes"] => struct_fields::<ReleaseNotesConfig>(),
+        ["release", "version"] => struct_fields::<VersionConfig>(),
+        ["release", "resources", index] if index.parse::<usize>().is_ok() => {
+            struct_fields::<Resource>()
+        }
+        _ => return None,
+    };
+
+    closest(name, known.iter().copied())
+}
+
+/// Matches a branch or tag name against a glob pattern
+///
+/// `*` matches within one path segment, `**` across segments and `?` any
+/// single character other than `/`.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    let pattern: Vec<char> = pattern.chars().collect();
+    let name: Vec<char> = name.chars().collect();
+
+    // Results by position in the pattern and the name, so repeated
+    // wildcards don't retry the same suffixes over and over
+    let mut memo = vec![vec![None; name.len() + 1]; pattern.len() + 1];
+
+    fn matches(
+        pattern: &[char],
+        name: &[char],
+        p: usize,
+        n: usize,
+        memo: &mut [Vec<Option<bool>>],
+    ) -> bool {
+        if let Some(result) = memo[p][n] {
+            return result;
+        }
+        let result = match &pattern[p..] {
+            [] => n == name.len(),
+            ['*', '*', ..] => (n..=name.len()).any(|i| matches(pattern, name, p + 2, i, memo)),
+            ['*', ..] => (n..=name.len())
+                .take_while(|&i| i == n || name[i - 1] != '/')
+                .any(|i| matches(pattern, name, p + 1, i, memo)),
+            ['?', ..] => {
+                matches!(name.get(n), Some(&c) if c != '/')
+                    && matches(pattern, name, p + 1, n + 1, memo)
+            }
+            [c, ..] => name.get(n) == Some(c) && matches(pattern, name, p + 1, n + 1, memo),
+        };
+        memo[p][n] = Some(result);
+        result
+    }
+
+    matches(&pattern, &name, 0, 0, &mut memo)
+}
+
+/// Returns the candidate closest to `name`, if any is close enough to be a typo
+fn closest<'a>(name: &str, candidates: impl Iterator<Item = &'a str>) -> Option<&'a str> {
+    // Allow roughly one typo per three characters
+    let max_distance = (name.len() / 3).max(1);
+    candidates
+        .map(|key| (strsim::damerau_levenshtein(name, key), key))
+        .filter(|&(distance, _)| distance <= max_distance)
+        .min_by_key(|&(distance, _)| distance)
+        .map(|(_, key)| key)
+}
+
+/// Returns the field names a derived `Deserialize` implementation accepts
+///
+/// Derived implementations pass their field names to `deserialize_struct`,
+/// so a deserializer that only records them keeps suggestions in sync with
+/// the structs.
+fn struct_fields<'de, T: Deserialize<'de>>() -> &'static [&'static str] {
+    struct FieldRecorder<'a>(&'a mut &'static [&'static str]);
+
+    impl<'de> Deserializer<'de> for FieldRecorder<'_> {
+        type Error = de::value::Error;
+
+        fn deserialize_any<V: Visitor<'de>>(self, _visitor: V) -> Result<V::Value, Self::Error> {
+            Err(de::Error::custom("only structs are supported"))
+        }
+
+        fn deserialize_struct<V: Visitor<'de>>(
+            self,
+            _name: &'static str,
+            fields: &'static [&'static str],
+            _visitor: V,
+        ) -> Result<V::Value, Self::Error> {
+            *self.0 = fields;
+            Err(de::Error::custom("fields recorded"))
+        }
+
+        serde::forward_to_deserialize_any! {
+            bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string
+            bytes byte_buf option unit unit_struct newtype_struct seq tuple
+            tuple_struct map enum identifier ignored_any
+        }
+    }
+
+    let mut fields: &'static [&'static str] = &[];
+    let _ = T::deserialize(FieldRecorder(&mut fields));
+    fields
+}
+
+/// Formats a path reported by `serde_ignored` as a dotted key path
+fn key_path(path: &serde_ignored::Path) -> String {
+    match path {
+        serde_ignored::Path::Root => String::new(),
+        serde_ignored::Path::Seq { parent, index } => join_key_path(parent, &index.to_string()),
+        serde_ignored::Path::Map { parent, key } => join_key_path(parent, key),
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => key_path(parent),
+    }
+}
+
+/// Appends a segment to the dotted path of `parent`
+fn join_key_path(parent: &serde_ignored::Path, segment: &str) -> String {
+    let parent = key_path(parent);
+    if parent.is_empty() {
+        segment.to_string()
+    } else {
+        format!("{}.{}", parent, segment)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn glob_star_stays_within_a_segment() {
+        assert!(glob_match("release/*", "release/1.2"));
+        assert!(glob_match("*", "main"));
+        assert!(!glob_match("release/*", "release/1.2/hotfix"));
+        assert!(!glob_match("*", "feature/x"));
+        assert!(glob_match("release/*/hotfix", "release/1.2/hotfix"));
+    }
+
+    #[test]
+    fn glob_double_star_crosses_segments() {
+        assert!(glob_match("release/**", "release/1.2/hotfix"));
+        assert!(glob_match("release/**", "release/"));
+        assert!(glob_match("**", "feature/a/b"));
+        assert!(glob_match("**/hotfix", "release/1.2/hotfix"));
+        assert!(!glob_match("release/**", "main"));
+    }
+
+    #[test]
+    fn glob_question_mark_matches_one_character_but_not_a_slash() {
+        assert!(glob_match("v?.0", "v1.0"));
+        assert!(!glob_match("v?.0", "v10.0"));
+        assert!(!glob_match("a?b", "a/b"));
+    }
+
+    #[test]
+    fn glob_matches_literally_without_wildcards() {
+        assert!(glob_match("main", "main"));
+        assert!(!glob_match("main", "main2"));
+        assert!(!glob_match("main", "mai"));
+    }
+
+    #[test]
+    fn glob_handles_many_wildcards_quickly() {
+        let pattern = "*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*a*b";
+        let name = "a".repeat(60);
+        assert!(!glob_match(pattern, &name));
+        assert!(!glob_match(&pattern.replace('*', "**"), &name));
+    }
 }
@@ -0,0 +1,995 @@
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::{self, IsTerminal, Write};
+use std::panic::{self, AssertUnwindSafe};
+use std::path::Path;
+use std::sync::{mpsc, Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::condition::{self, Context};
+use crate::config::{Config, Resource, DEFAULT_TAG_NAME};
+use crate::error::DeployError;
+use crate::git;
+use crate::history::{History, HistoryEntry};
+use crate::manifest::Manifest;
+use crate::notes;
+use crate::resources;
+use crate::signature;
+use crate::template;
+use crate::version::{self, Bump, ReleaseVersion};
+
+/// Options controlling how a deployment runs
+#[derive(Debug, Default, Clone)]
+pub struct DeployOptions {
+    /// Enable verbose output
+    pub verbose: bool,
+    /// Keep the checkout directory after deployment (only applies to clean mode)
+    pub keep_checkout: bool,
+    /// Show the plan without checking out or running anything
+    pub dry_run: bool,
+    /// Environment being deployed to, available to `when:` conditions
+    pub env: Option<String>,
+    /// Deploy without asking, even where confirmation is required
+    pub assume_yes: bool,
+    /// Print `git diff --stat` of the changes being deployed
+    pub show_diff_stat: bool,
+    /// Version component to increase, for releases with `version`; inferred from commits if unset
+    pub bump: Option<Bump>,
+    /// Deploy even if the branch breaks `allowed_branches` or `require_tag_pattern`
+    pub override_policy: bool,
+}
+
+/// Changelog that release notes are prepended to, relative to the checkout
+const CHANGELOG_FILE: &str = "CHANGELOG.md";
+
+/// Held while a release shows its changes and asks for confirmation, so
+/// releases running in parallel ask one at a time
+static PROMPT: Mutex<()> = Mutex::new(());
+
+/// Runs a release: checkout, resources, tool and cleanup
+#[derive(Debug)]
+pub struct Deployment {
+    config_path: String,
+    config: Config,
+    options: DeployOptions,
+    name: Option<String>,
+}
+
+impl Deployment {
+    /// Creates a deployment for an already loaded configuration
+    ///
+    /// # Arguments
+    /// * `config_path` - Path to the configuration file (used to locate resources)
+    /// * `config` - Validated configuration
+    /// * `options` - Options controlling the run
+    pub fn new(config_path: impl Into<String>, config: Config, options: DeployOptions) -> Self {
+        Deployment {
+            config_path: config_path.into(),
+            config,
+            options,
+            name: None,
+        }
+    }
+
+    /// Names the deployment after its entry under `releases`, which keeps its history apart
+    pub fn with_name(mut self, name: impl Into<String>) -> Self {
+        self.name = Some(name.into());
+        self
+    }
+
+    /// Loads and validates the configuration at `config_path`
+    pub fn from_path(config_path: &str, options: DeployOptions) -> Result<Self, DeployError> {
+        let config = Config::load(config_path)?;
+        config.validate_with(options.override_policy)?;
+        Ok(Deployment::new(config_path, config, options))
+    }
+
+    /// Returns the configuration being deployed
+    pub fn config(&self) -> &Config {
+        &self.config
+    }
+
+    /// Returns whether the deployment asks for confirmation before running
+    ///
+    /// That is when the release sets `confirm: true` or the environment is
+    /// one of its `protected_environments`, unless this is a dry run.
+    pub fn needs_confirmation(&self) -> bool {
+        let release = &self.config.release;
+        let protected = self
+            .options
+            .env
+            .as_ref()
+            .is_some_and(|env| release.protected_environments.contains(env));
+        (release.confirm || protected) && !self.options.dry_run
+    }
+
+    /// Returns whether the deployment will stop to ask, i.e. needs confirmation without `assume_yes`
+    fn asks_confirmation(&self) -> bool {
+        self.needs_confirmation() && !self.options.assume_yes
+    }
+
+    /// Checks up front that a confirmation, if one is needed, can be asked for
+    ///
+    /// # Returns
+    /// [`DeployError::Aborted`] if the deployment asks for confirmation and
+    /// standard input is not a terminal
+    pub fn check_confirmable(&self) -> Result<(), DeployError> {
+        if self.asks_confirmation() && !io::stdin().is_terminal() {
+            return Err(DeployError::Aborted(format!(
+                "deploying to {} needs confirmation, but standard input is not a terminal; pass --yes to confirm",
+                self.confirmation_answer()
+            )));
+        }
+        Ok(())
+    }
+
+    /// Returns what has to be typed to confirm: the environment, or the branch without one
+    fn confirmation_answer(&self) -> &str {
+        self.options
+            .env
+            .as_deref()
+            .unwrap_or(&self.config.release.branch)
+    }
+
+    /// Shows the plan for the checked-out commit and asks for the environment name to be typed back
+    ///
+    /// Does nothing if no confirmation is needed or `assume_yes` is set.
+    /// Without an environment, the branch name is asked for instead.
+    ///
+    /// # Arguments
+    /// * `commit` - Commit that was checked out
+    ///
+    /// # Returns
+    /// `Ok` to go ahead, or [`DeployError::Aborted`] if the answer didn't
+    /// match or standard input is not a terminal
+    fn confirm(&self, commit: &str) -> Result<(), DeployError> {
+        if !self.asks_confirmation() {
+            return Ok(());
+        }
+        self.check_confirmable()?;
+
+        let release = &self.config.release;
+        let expected = self.confirmation_answer();
+        println!("Deployment plan:");
+        if let Some(name) = &self.name {
+            println!("  Release: {}", name);
+        }
+        if let Some(env) = &self.options.env {
+            println!("  Environment: {}", env);
+        }
+        println!("  Repository: {}", release.repository);
+        println!("  Branch: {}", release.branch);
+        println!("  Commit: {}", commit);
+        if release.resources.is_empty() {
+            println!("  Resources: (none)");
+        } else {
+            println!("  Resources:");
+            for resource in &release.resources {
+                println!("    {} -> {}", resource.file, resource.destination());
+            }
+        }
+        if release.tool.is_empty() {
+            println!("  Tool: (none)");
+        } else {
+            println!("  Tool: {}", release.tool);
+        }
+
+        print!("Type '{}' to deploy: ", expected);
+        io::stdout().flush()?;
+        let mut answer = String::new();
+        io::stdin().read_line(&mut answer)?;
+
+        if answer.trim() != expected {
+            return Err(DeployError::Aborted(format!(
+                "expected '{}' to confirm",
+                expected
+            )));
+        }
+        Ok(())
+    }
+
+    /// Executes the deployment workflow, asking for confirmation where required
+    pub fn run(&self) -> Result<(), DeployError> {
+        self.check_confirmable()?;
+        self.execute()
+    }
+
+    /// Executes the deployment workflow
+    ///
+    /// A confirmation is asked for once the commit is checked out and its
+    /// changes are shown, before resources are copied or the tool runs.
+    fn execute(&self) -> Result<(), DeployError> {
+        let config = &self.config;
+        let config_path = self.config_path.as_str();
+        let verbose = self.options.verbose;
+
+        if self.options.dry_run {
+            println!("Dry run: no repository will be checked out and no tool will be executed");
+
+            // In non-clean mode the checkout is the current directory and already exists
+            let current_dir = std::env::current_dir()?;
+            let context = Context {
+                env: self.options.env.as_deref(),
+                branch: &config.release.branch,
+                variables: &config.release.variables,
+                repo_path: (!config.release.clean).then_some(current_dir.as_path()),
+            };
+            return resources::print_plan(config_path, config, &context);
+        }
+
+        // Taken before the checkout, which may update the current directory
+        let previous = self.previous_commit();
+
+        // Perform repository checkout
+        let repo_path = git::prepare_repository(
+            &config.release.repository,
+            &config.release.branch,
+            config.release.clean,
+            config.release.merge,
+            verbose,
+        )?;
+        let commit = match git::head_commit(&repo_path) {
+            Ok(commit) => commit,
+            Err(e) => {
+                self.discard_checkout(&repo_path);
+                return Err(e);
+            }
+        };
+
+        // Validation has passed, so any policy violation was overridden
+        let mut policy_overridden = !config.policy_problems().is_empty()
+            || config
+                .overrides
+                .iter()
+                .any(|item| item.changes_policy(self.name.as_deref()));
+
+        // Whatever happens once the repository is checked out is cleaned up after and recorded
+        let result = self.deploy_checkout(
+            &repo_path,
+            previous.as_deref(),
+            &commit,
+            &mut policy_overridden,
+        );
+        let result = self.clean_up(&repo_path, result);
+
+        let mut entry = HistoryEntry::now(
+            self.name.as_deref(),
+            self.options.env.as_deref(),
+            &config.release.repository,
+            &config.release.branch,
+            &commit,
+            result.is_ok(),
+        );
+        entry.policy_overridden = policy_overridden;
+        if let Err(e) = History::append(&std::env::current_dir()?, entry) {
+            eprintln!(
+                "Warning: Failed to record the deployment in the history: {}",
+                e
+            );
+        }
+
+        result
+    }
+
+    /// Deploys a checkout: checks it, asks for confirmation, copies resources and runs the tool
+    ///
+    /// The caller cleans up after the checkout, whether this succeeds or not.
+    ///
+    /// # Arguments
+    /// * `repo_path` - Checkout being deployed
+    /// * `previous` - Commit deployed last time, if known
+    /// * `commit` - Commit being deployed
+    /// * `policy_overridden` - Set if a policy violation is overridden
+    fn deploy_checkout(
+        &self,
+        repo_path: &Path,
+        previous: Option<&str>,
+        commit: &str,
+        policy_overridden: &mut bool,
+    ) -> Result<(), DeployError> {
+        let config = &self.config;
+        let verbose = self.options.verbose;
+
+        *policy_overridden |= self.check_tag(repo_path)?;
+        // Nothing from the checkout is used before its signature is trusted
+        if let Some(check) = config.release.verify_signature {
+            signature::verify(repo_path, check, &config.release.allowed_signers, verbose)?;
+        }
+        // A push is refused if the remote branch moves after this point
+        let upstream = if config.release.push {
+            git::tracking_commit(repo_path, &config.release.branch)?
+        } else {
+            None
+        };
+
+        // The version is known once the tags are checked out, and resources may use it
+        let mut variables = config.release.variables.clone();
+        let mut resources = config.release.resources.clone();
+        let version = self.apply_version(repo_path, &mut variables, &mut resources)?;
+        let context = Context {
+            env: self.options.env.as_deref(),
+            branch: &config.release.branch,
+            variables: &variables,
+            repo_path: None,
+        };
+
+        if verbose {
+            println!(
+                "Repository successfully checked out to: {}",
+                repo_path.display()
+            );
+        } else {
+            println!("Repository checked out successfully");
+        }
+
+        // Reviewers see what is going out before they confirm it
+        if self.asks_confirmation() {
+            let _prompt = PROMPT.lock().unwrap_or_else(PoisonError::into_inner);
+            self.print_changes(repo_path, previous, commit);
+            self.confirm(commit)?;
+        } else {
+            self.print_changes(repo_path, previous, commit);
+        }
+
+        git::copy_release_resources(
+            &self.config_path,
+            repo_path,
+            config.release.clean,
+            &resources,
+            &config.release.resources_dir,
+            &context,
+            verbose,
+        )?;
+
+        self.finish(
+            repo_path,
+            &context,
+            previous,
+            commit,
+            upstream.as_deref(),
+            version.as_ref(),
+        )
+    }
+
+    /// Determines the version of a checkout and fills it into the variables and resources
+    ///
+    /// # Arguments
+    /// * `repo_path` - Checkout being deployed
+    /// * `variables` - Variables to add `version` to
+    /// * `resources` - Resources whose `copy` destinations may use `${version}`
+    ///
+    /// # Returns
+    /// The version, if the release has `version`
+    fn apply_version(
+        &self,
+        repo_path: &Path,
+        variables: &mut BTreeMap<String, String>,
+        resources: &mut [Resource],
+    ) -> Result<Option<ReleaseVersion>, DeployError> {
+        let Some(version_config) = &self.config.release.version else {
+            return Ok(None);
+        };
+
+        let version = version::determine(repo_path, version_config, self.options.bump)?;
+        print_version(&version);
+        variables.insert("version".to_string(), version.version.to_string());
+        for resource in resources {
+            if let Some(copy) = &resource.copy_path {
+                resource.copy_path = Some(version::substitute(copy, &version.version)?);
+            }
+        }
+        Ok(Some(version))
+    }
+
+    /// Checks that a release with `require_tag_pattern` checked out a tag
+    ///
+    /// The pattern was checked against the name; the checkout shows whether
+    /// it's a tag.
+    ///
+    /// # Returns
+    /// Whether the violation was overridden with `--override-policy`
+    fn check_tag(&self, repo_path: &Path) -> Result<bool, DeployError> {
+        let release = &self.config.release;
+        if release.require_tag_pattern.is_none()
+            || git::tags_at(repo_path, "HEAD")?.contains(&release.branch)
+        {
+            return Ok(false);
+        }
+
+        let message = format!(
+            "'{}' must be a tag matching `require_tag_pattern`, but isn't a tag",
+            release.branch
+        );
+        if !self.options.override_policy {
+            return Err(DeployError::ValidationError(format!(
+                "{} (pass --override-policy to deploy anyway)",
+                message
+            )));
+        }
+        eprintln!("Warning: Overriding policy: {}", message);
+        Ok(true)
+    }
+
+    /// Removes a clean checkout that won't be deployed, unless it is to be kept
+    fn discard_checkout(&self, repo_path: &Path) {
+        if self.config.release.clean && !self.options.keep_checkout {
+            let _ = fs::remove_dir_all(repo_path);
+        }
+    }
+
+    /// Returns the commit deployed last time, from the history or the current checkout
+    fn previous_commit(&self) -> Option<String> {
+        let release = &self.config.release;
+        let current_dir = std::env::current_dir().ok()?;
+
+        match History::load(&current_dir) {
+            Ok(history) => {
+                let entry = history.last_success(
+                    self.name.as_deref(),
+                    self.options.env.as_deref(),
+                    &release.repository,
+                );
+                if let Some(entry) = entry {
+                    return Some(entry.commit.clone());
+                }
+            }
+            Err(e) => eprintln!("Warning: {}", e),
+        }
+
+        // A non-clean deployment updates the checkout in the current directory
+        if !release.clean && current_dir.join(".git").exists() {
+            return git::head_commit(&current_dir).ok();
+        }
+        None
+    }
+
+    /// Prints the commits going out with this deployment
+    ///
+    /// # Arguments
+    /// * `repo_path` - Checkout being deployed
+    /// * `previous` - Commit deployed last time, if known
+    /// * `commit` - Commit being deployed
+    fn print_changes(&self, repo_path: &Path, previous: Option<&str>, commit: &str) {
+        let Some(previous) = previous else {
+            println!(
+                "Deploying {} (no previous deployment recorded)",
+                git::short_sha(commit)
+            );
+            return;
+        };
+        if previous == commit {
+            println!(
+                "Deploying {} (no changes since the last deployment)",
+                git::short_sha(commit)
+            );
+            return;
+        }
+        if !git::has_commit(repo_path, previous) {
+            println!(
+                "Deploying {}; the previously deployed {} is not in the repository history",
+                git::short_sha(commit),
+                git::short_sha(previous)
+            );
+            return;
+        }
+
+        let commits = match git::commits_between(repo_path, Some(previous), commit) {
+            Ok(commits) => commits,
+            Err(e) => {
+                eprintln!("Warning: Failed to list the commits being deployed: {}", e);
+                return;
+            }
+        };
+
+        if commits.is_empty() {
+            // The previous commit is ahead, so this deployment goes back
+            let reverted = git::commits_between(repo_path, Some(commit), previous)
+                .map_or(0, |commits| commits.len());
+            println!(
+                "Deploying {}, rolling back {} commit{} deployed with {}",
+                git::short_sha(commit),
+                reverted,
+                if reverted == 1 { "" } else { "s" },
+                git::short_sha(previous)
+            );
+        } else {
+            let mut authors: Vec<(&str, usize)> = Vec::new();
+            for info in &commits {
+                match authors.iter_mut().find(|(name, _)| *name == info.author) {
+                    Some((_, count)) => *count += 1,
+                    None => authors.push((&info.author, 1)),
+                }
+            }
+            let authors: Vec<String> = authors
+                .iter()
+                .map(|(name, count)| format!("{} ({})", name, count))
+                .collect();
+
+            println!(
+                "Deploying {} commit{} since {} by {}:",
+                commits.len(),
+                if commits.len() == 1 { "" } else { "s" },
+                git::short_sha(previous),
+                authors.join(", ")
+            );
+            for info in &commits {
+                println!("  {} {}", git::short_sha(&info.sha), info.subject);
+            }
+        }
+
+        if self.options.show_diff_stat {
+            match git::diff_stat(repo_path, previous, commit) {
+                Ok(stat) => print!("{}", stat),
+                Err(e) => eprintln!("Warning: Failed to compute the diff stat: {}", e),
+            }
+        }
+    }
+
+    /// Writes release notes and creates the tag, as configured
+    ///
+    /// # Arguments
+    /// * `repo_path` - Checkout being deployed
+    /// * `previous` - Commit deployed last time, if known
+    /// * `commit` - Commit being deployed
+    /// * `version` - Version being deployed, for releases with `version`
+    ///
+    /// # Returns
+    /// The name of the tag that was created, if any
+    fn tag_release(
+        &self,
+        repo_path: &Path,
+        previous: Option<&str>,
+        commit: &str,
+        version: Option<&ReleaseVersion>,
+    ) -> Result<Option<String>, DeployError> {
+        let release = &self.config.release;
+        if !release.tag && release.release_notes.is_none() {
+            return Ok(None);
+        }
+
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |elapsed| elapsed.as_secs());
+        let (year, month, day, hour, minute, second) = notes::utc_datetime(now);
+        let date = format!("{:04}-{:02}-{:02}", year, month, day);
+        let mut values = BTreeMap::from([
+            ("branch", release.branch.clone()),
+            ("date", date.clone()),
+            ("env", self.options.env.clone().unwrap_or_default()),
+            (
+                "timestamp",
+                format!(
+                    "{:04}{:02}{:02}{:02}{:02}{:02}",
+                    year, month, day, hour, minute, second
+                ),
+            ),
+        ]);
+        if let Some(version) = version {
+            values.insert("version", version.version.to_string());
+        }
+        let tag_name = match (&release.tag_name, version) {
+            (Some(template), _) => {
+                template::expand(template, &values).map_err(DeployError::ValidationError)?
+            }
+            (None, Some(version)) => version.tag.clone(),
+            (None, None) => {
+                template::expand(DEFAULT_TAG_NAME, &values).map_err(DeployError::ValidationError)?
+            }
+        };
+        // Redeploying a tagged version without changes keeps its tag
+        let tag_exists = version.is_some_and(|v| v.is_tagged() && v.tag == tag_name);
+
+        let mut message = format!("Deploy {} from {}", git::short_sha(commit), release.branch);
+
+        if let Some(notes_config) = &release.release_notes {
+            // Without a usable previous deployment, the notes start after the latest tag
+            let start = previous
+                .filter(|&sha| git::has_commit(repo_path, sha))
+                .map(String::from)
+                .or_else(|| git::latest_tag(repo_path, commit));
+            let commits = git::commits_between(repo_path, start.as_deref(), commit)?;
+
+            let title = if release.tag {
+                tag_name.clone()
+            } else {
+                git::short_sha(commit)
+            };
+            let text = notes::render(&title, &date, &commits);
+
+            if let Some(file) = &notes_config.file {
+                fs::write(file, &text)?;
+                println!("Release notes written to {}", file);
+            }
+            if notes_config.changelog {
+                notes::prepend_to_changelog(&repo_path.join(CHANGELOG_FILE), &text)?;
+                git::commit_file(
+                    repo_path,
+                    CHANGELOG_FILE,
+                    &format!("Update changelog for {}", title),
+                )?;
+                println!("Release notes added to {}", CHANGELOG_FILE);
+            }
+            if notes_config.tag_message {
+                message = text;
+            }
+        }
+
+        if release.tag && tag_exists {
+            println!("Tag {} already exists", tag_name);
+        } else if release.tag {
+            git::create_tag(repo_path, &tag_name, &message)?;
+            println!("Created tag {}", tag_name);
+            return Ok(Some(tag_name));
+        }
+        Ok(None)
+    }
+
+    /// Pushes the checkout's branch and the created tag, if the release pushes
+    ///
+    /// # Arguments
+    /// * `repo_path` - Checkout being deployed
+    /// * `upstream` - Commit the remote branch pointed to at checkout
+    /// * `tag` - Tag created for the deployment, if any
+    fn push(
+        &self,
+        repo_path: &Path,
+        upstream: Option<&str>,
+        tag: Option<String>,
+    ) -> Result<(), DeployError> {
+        let release = &self.config.release;
+        if !release.push {
+            return Ok(());
+        }
+
+        let head = git::head_commit(repo_path)?;
+        if tag.is_none() && upstream == Some(head.as_str()) {
+            println!("Nothing to push to {}", release.branch);
+            return Ok(());
+        }
+
+        let tags: Vec<String> = tag.into_iter().collect();
+        git::push(
+            repo_path,
+            &release.branch,
+            &tags,
+            upstream,
+            release.force_with_lease,
+            self.options.verbose,
+        )?;
+        match tags.first() {
+            Some(tag) => println!("Pushed {} and tag {}", release.branch, tag),
+            None => println!("Pushed {}", release.branch),
+        }
+        Ok(())
+    }
+
+    /// Runs the tool in a checkout, then tags, pushes and merges back the release
+    ///
+    /// # Arguments
+    /// * `repo_path` - Checkout being deployed
+    /// * `context` - Context for the tool's `when` condition
+    /// * `previous` - Commit deployed last time, if known
+    /// * `commit` - Commit being deployed
+    /// * `upstream` - Commit the remote branch pointed to at checkout, for releases that push
+    /// * `version` - Version being deployed, for releases with `version`
+    fn finish(
+        &self,
+        repo_path: &Path,
+        context: &Context,
+        previous: Option<&str>,
+        commit: &str,
+        upstream: Option<&str>,
+        version: Option<&ReleaseVersion>,
+    ) -> Result<(), DeployError> {
+        let config = &self.config;
+        let verbose = self.options.verbose;
+        let repo_path = repo_path.to_path_buf();
+
+        // Evaluate the tool condition against the checkout
+        let run_tool = match config.release.tool.condition() {
+            Some(when) => {
+                let context = Context {
+                    repo_path: Some(&repo_path),
+                    ..*context
+                };
+                let run_tool = condition::evaluate(when, &context)?;
+                if !run_tool {
+                    println!("Skipping tool '{}' (when: {})", config.release.tool, when);
+                }
+                run_tool
+            }
+            None => true,
+        };
+
+        // Execute deployment tool if specified
+        let tool_command = config.release.tool.command().filter(|_| run_tool);
+        let tool_result = if let Some(command) = tool_command {
+            if verbose {
+                println!("Executing tool: '{}'", config.release.tool);
+            }
+            // The version is passed in the environment and in place of `${version}`
+            let version_text = version.map(|v| v.version.to_string());
+            let env: Vec<(&str, &str)> = version_text
+                .iter()
+                .map(|text| (version::ENV_VAR, text.as_str()))
+                .collect();
+            let arguments = config.release.tool.arguments();
+            let arguments = match version {
+                Some(version) => arguments
+                    .iter()
+                    .map(|argument| version::substitute(argument, &version.version))
+                    .collect(),
+                None => Ok(arguments.to_vec()),
+            };
+            // A tool that can't be started still leaves resources to clean up
+            match arguments.and_then(|arguments| {
+                git::execute_tool_with_env(command, &arguments, &env, &repo_path, verbose)
+            }) {
+                Ok(0) => {
+                    if verbose {
+                        println!("Tool execution completed successfully");
+                    }
+                    Ok(())
+                }
+                Ok(exit_code) => Err(DeployError::ToolFailed {
+                    tool: config.release.tool.to_string(),
+                    code: exit_code,
+                }),
+                Err(e) => Err(e),
+            }
+        } else {
+            Ok(())
+        };
+
+        // Only a successful deployment is tagged, pushed and merged back
+        let tool_result = tool_result
+            .and_then(|()| self.tag_release(&repo_path, previous, commit, version))
+            .and_then(|tag| self.push(&repo_path, upstream, tag))
+            .and_then(|()| {
+                for target in &config.release.merge_back {
+                    git::merge_back(&repo_path, &config.release.branch, target, verbose)?;
+                    println!("Merged {} into {}", config.release.branch, target);
+                }
+                Ok(())
+            });
+
+        tool_result
+    }
+
+    /// Removes or restores copied resources, and removes a clean checkout unless it is kept
+    ///
+    /// # Arguments
+    /// * `repo_path` - Checkout being deployed
+    /// * `tool_result` - Outcome of the deployment so far
+    ///
+    /// # Returns
+    /// `tool_result`, or the cleanup failure if the deployment had succeeded;
+    /// cleanup failures after a failed deployment are printed as warnings
+    fn clean_up(
+        &self,
+        repo_path: &Path,
+        tool_result: Result<(), DeployError>,
+    ) -> Result<(), DeployError> {
+        let config = &self.config;
+        let verbose = self.options.verbose;
+
+        // Remove or restore copied resources in non-clean mode
+        if !config.release.clean {
+            let cleanup = Manifest::load(repo_path).and_then(|mut manifest| {
+                manifest.cleanup(repo_path, config.release.resources_cleanup, verbose)
+            });
+            if let Err(e) = cleanup {
+                if tool_result.is_ok() {
+                    return Err(DeployError::CleanupFailed(format!(
+                        "Failed to clean up resources: {}",
+                        e
+                    )));
+                }
+                eprintln!("Warning: Failed to clean up resources: {}", e);
+            }
+        }
+
+        // Cleanup checkout directory if in clean mode and not keeping it
+        if config.release.clean && !self.options.keep_checkout {
+            if let Err(e) = std::fs::remove_dir_all(repo_path) {
+                if tool_result.is_ok() {
+                    return Err(DeployError::CleanupFailed(format!(
+                        "Failed to remove checkout directory {}: {}",
+                        repo_path.display(),
+                        e
+                    )));
+                }
+                eprintln!("Warning: Failed to remove checkout directory: {}", e);
+            } else if verbose {
+                println!("Removed checkout directory: {}", repo_path.display());
+            }
+        } else if config.release.clean && verbose {
+            println!("Keeping checkout directory: {}", repo_path.display());
+        }
+
+        tool_result
+    }
+}
+
+/// Prints the version being deployed and how it was arrived at
+fn print_version(version: &ReleaseVersion) {
+    match (&version.previous_tag, version.bump) {
+        (Some(previous), Some(bump)) => println!(
+            "Version: {} ({} bump from {})",
+            version.version, bump, previous
+        ),
+        (Some(previous), None) => {
+            println!(
+                "Version: {} (no changes since {})",
+                version.version, previous
+            )
+        }
+        (None, _) => println!("Version: {} (no version tag yet)", version.version),
+    }
+}
+
+/// How a release ended in a multi-release run
+#[derive(Debug)]
+pub enum ReleaseStatus {
+    /// The release ran successfully
+    Succeeded,
+    /// The release ran and failed
+    Failed(DeployError),
+    /// The release didn't run because a release it depends on didn't succeed
+    Skipped {
+        /// Name of the dependency that failed or was skipped
+        dependency: String,
+    },
+}
+
+/// Result of one release in a multi-release run
+#[derive(Debug)]
+pub struct ReleaseOutcome {
+    /// Name of the release under `releases`
+    pub name: String,
+    /// How the release ended
+    pub status: ReleaseStatus,
+}
+
+/// Runs several named releases, each once the releases it depends on have succeeded
+///
+/// Releases whose dependencies failed are skipped; independent releases
+/// carry on. A release that needs confirmation asks once its own checkout
+/// is ready, so releases that ran before it stay deployed if it is
+/// declined. Nothing runs if a confirmation can't be asked for.
+///
+/// # Arguments
+/// * `deployments` - Releases to run with their names, ordered so that
+///   dependencies come first (see [`Config::release_order`])
+/// * `jobs` - Maximum number of releases running at the same time
+///
+/// # Returns
+/// The outcome of every release, in the order given
+pub fn run_releases(
+    deployments: Vec<(String, Deployment)>,
+    jobs: usize,
+) -> Result<Vec<ReleaseOutcome>, DeployError> {
+    let jobs = jobs.max(1);
+
+    // Non-clean releases all work in the current directory
+    if jobs > 1 && deployments.len() > 1 {
+        if let Some((name, _)) = deployments
+            .iter()
+            .find(|(_, deployment)| !deployment.config.release.clean)
+        {
+            return Err(DeployError::ValidationError(format!(
+                "Release '{}' deploys into the current directory and can't run in parallel; set `clean: true` or run one job at a time",
+                name
+            )));
+        }
+    }
+
+    // Refused before anything runs, rather than halfway through
+    for (_, deployment) in &deployments {
+        deployment.check_confirmable()?;
+    }
+
+    // Dependencies outside the selection are assumed to be deployed already
+    let dependencies: Vec<Vec<usize>> = deployments
+        .iter()
+        .map(|(_, deployment)| {
+            deployment
+                .config
+                .release
+                .depends_on
+                .iter()
+                .filter_map(|dependency| deployments.iter().position(|(n, _)| n == dependency))
+                .collect()
+        })
+        .collect();
+
+    let mut statuses: Vec<Option<ReleaseStatus>> = deployments.iter().map(|_| None).collect();
+    let mut started = vec![false; deployments.len()];
+
+    std::thread::scope(|scope| {
+        let (sender, receiver) = mpsc::channel();
+        let mut running = 0;
+
+        loop {
+            // Dependencies come first, so one pass settles skips transitively
+            for i in 0..deployments.len() {
+                if started[i] {
+                    continue;
+                }
+
+                let blocked = dependencies[i].iter().find(|&&d| {
+                    matches!(
+                        statuses[d],
+                        Some(ReleaseStatus::Failed(_) | ReleaseStatus::Skipped { .. })
+                    )
+                });
+                if let Some(&dependency) = blocked {
+                    started[i] = true;
+                    statuses[i] = Some(ReleaseStatus::Skipped {
+                        dependency: deployments[dependency].0.clone(),
+                    });
+                    continue;
+                }
+
+                let ready = dependencies[i]
+                    .iter()
+                    .all(|&d| matches!(statuses[d], Some(ReleaseStatus::Succeeded)));
+                if ready && running < jobs {
+                    started[i] = true;
+                    running += 1;
+
+                    let (name, deployment) = &deployments[i];
+                    println!("\n==> Release '{}'", name);
+                    let sender = sender.clone();
+                    scope.spawn(move || {
+                        let result = panic::catch_unwind(AssertUnwindSafe(|| deployment.execute()));
+                        // The receiver outlives every release
+                        let _ = sender.send((i, result));
+                    });
+                }
+            }
+
+            if running == 0 {
+                break;
+            }
+
+            let (i, result) = receiver.recv().expect("A release is still running");
+            running -= 1;
+            statuses[i] = Some(match result {
+                Ok(Ok(())) => ReleaseStatus::Succeeded,
+                Ok(Err(e)) => ReleaseStatus::Failed(e),
+                Err(panic) => panic::resume_unwind(panic),
+            });
+        }
+    });
+
+    Ok(deployments
+        .into_iter()
+        .zip(statuses)
+        .map(|((name, _), status)| ReleaseOutcome {
+            name,
+            status: status.expect("Every release is run or skipped"),
+        })
+        .collect())
+}
+
+/// Prints one line per release saying whether it succeeded
+pub fn print_outcomes(outcomes: &[ReleaseOutcome]) {
+    let succeeded = outcomes
+        .iter()
+        .filter(|o| matches!(o.status, ReleaseStatus::Succeeded))
+        .count();
+
+    println!("\nSummary:");
+    for outcome in outcomes {
+        match &outcome.status {
+            ReleaseStatus::Succeeded => println!("  {}: succeeded", outcome.name),
+            ReleaseStatus::Failed(e) => println!("  {}: failed: {}", outcome.name, e),
+            ReleaseStatus::Skipped { dependency } => println!(
+                "  {}: skipped, '{}' did not succeed",
+                outcome.name, dependency
+            ),
+        }
+    }
+    println!("{} of {} releases succeeded", succeeded, outcomes.len());
+}
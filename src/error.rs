@@ -0,0 +1,145 @@
+use std::path::PathBuf;
+use thiserror::Error;
+
+/// Errors that can occur while loading a configuration or deploying it
+#[derive(Debug, Error)]
+pub enum DeployError {
+    /// The configuration file does not exist
+    #[error("Config file does not exist: {0}")]
+    ConfigNotFound(String),
+
+    /// The configuration file exists but could not be read
+    #[error("Could not read config file {path}: {message}")]
+    ConfigUnreadable { path: String, message: String },
+
+    /// The configuration file could not be parsed
+    #[error("Failed to parse config file {path}: {message}")]
+    ParseError { path: String, message: String },
+
+    /// The configuration is invalid
+    #[error("{0}")]
+    ValidationError(String),
+
+    /// Cloning the repository failed
+    #[error("Git clone failed with exit code: {code}")]
+    CloneFailed { code: i32 },
+
+    /// Merging upstream changes failed
+    #[error("Git merge of {branch} failed with exit code: {code}")]
+    MergeConflict { branch: String, code: i32 },
+
+    /// The repository has uncommitted changes
+    #[error("Repository has uncommitted changes ({}). Commit or stash changes before updating.", .0.join(", "))]
+    DirtyWorktree(Vec<String>),
+
+    /// A path escapes the directory it must stay in
+    #[error("Path traversal attempt detected: {} is outside {}", path.display(), base.display())]
+    PathTraversal { path: PathBuf, base: PathBuf },
+
+    /// The deployment tool exited with a non-zero code
+    #[error("Tool '{tool}' failed with exit code {code}")]
+    ToolFailed { tool: String, code: i32 },
+
+    /// Any other git command failed
+    #[error("{0}")]
+    Git(String),
+
+    /// A resource could not be located, verified or written
+    #[error("{0}")]
+    Resource(String),
+
+    /// A resource does not match its recorded checksum
+    #[error("Checksum mismatch for resource '{file}': expected sha256 {expected}, found {actual}")]
+    ChecksumMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// A resource does not match the hash recorded in the lockfile
+    #[error("Resource '{file}' does not match the lockfile: expected sha256 {expected}, found {actual}. Run `ud resources lock` if the change is intended.")]
+    LockfileMismatch {
+        file: String,
+        expected: String,
+        actual: String,
+    },
+
+    /// Encrypting or decrypting a file failed
+    #[error("{0}")]
+    Secrets(String),
+
+    /// A `when:` condition is invalid or could not be evaluated
+    #[error("{0}")]
+    Condition(String),
+
+    /// Another deployment holds the lock on the working directory
+    #[error("Another deployment is running ({}). Remove the lock file if it is stale.", .0.display())]
+    Locked(PathBuf),
+
+    /// Cleaning up after an otherwise successful deployment failed
+    #[error("Cleanup failed: {0}")]
+    CleanupFailed(String),
+
+    /// The deployment needed confirmation and didn't get it
+    #[error("Deployment aborted: {0}")]
+    Aborted(String),
+
+    /// The deployed commit isn't signed by an allowed key
+    #[error("Signature verification failed: {0}")]
+    UntrustedSignature(String),
+
+    /// An I/O operation failed
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+}
+
+/// Process exit codes, one per failure class
+pub mod exit_code {
+    /// Unexpected failure, e.g. an I/O error
+    pub const FAILURE: i32 = 1;
+    /// The configuration file is missing or can't be parsed
+    pub const CONFIG: i32 = 10;
+    /// The configuration is invalid
+    pub const VALIDATION: i32 = 11;
+    /// A git operation failed; often worth retrying
+    pub const GIT: i32 = 20;
+    /// A resource could not be located, verified, decrypted or written
+    pub const RESOURCE: i32 = 30;
+    /// The deployment tool failed
+    pub const TOOL: i32 = 40;
+    /// Another deployment holds the lock
+    pub const LOCKED: i32 = 50;
+    /// Cleaning up after the deployment failed
+    pub const CLEANUP: i32 = 60;
+    /// The deployment was not confirmed
+    pub const ABORTED: i32 = 70;
+    /// The deployed commit isn't signed by an allowed key
+    pub const SIGNATURE: i32 = 80;
+}
+
+impl DeployError {
+    /// Returns the process exit code for this error's failure class
+    pub fn exit_code(&self) -> i32 {
+        match self {
+            DeployError::ConfigNotFound(_)
+            | DeployError::ConfigUnreadable { .. }
+            | DeployError::ParseError { .. } => exit_code::CONFIG,
+            DeployError::ValidationError(_) | DeployError::Condition(_) => exit_code::VALIDATION,
+            DeployError::CloneFailed { .. }
+            | DeployError::MergeConflict { .. }
+            | DeployError::DirtyWorktree(_)
+            | DeployError::Git(_) => exit_code::GIT,
+            DeployError::PathTraversal { .. }
+            | DeployError::Resource(_)
+            | DeployError::ChecksumMismatch { .. }
+            | DeployError::LockfileMismatch { .. }
+            | DeployError::Secrets(_) => exit_code::RESOURCE,
+            DeployError::ToolFailed { .. } => exit_code::TOOL,
+            DeployError::Locked(_) => exit_code::LOCKED,
+            DeployError::CleanupFailed(_) => exit_code::CLEANUP,
+            DeployError::Aborted(_) => exit_code::ABORTED,
+            DeployError::UntrustedSignature(_) => exit_code::SIGNATURE,
+            DeployError::Io(_) => exit_code::FAILURE,
+        }
+    }
+}
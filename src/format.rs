@@ -0,0 +1,167 @@
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::error::DeployError;
+
+/// File format of a configuration
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigFormat {
+    #[default]
+    Yaml,
+    Toml,
+    Json,
+}
+
+impl ConfigFormat {
+    /// Names accepted by [`ConfigFormat::from_str`]
+    pub const NAMES: &'static [&'static str] = &["yaml", "toml", "json"];
+
+    /// Detects the format from a file extension (`.yml`, `.yaml`, `.toml` or `.json`)
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "yml" | "yaml" => Some(ConfigFormat::Yaml),
+            "toml" => Some(ConfigFormat::Toml),
+            "json" => Some(ConfigFormat::Json),
+            _ => None,
+        }
+    }
+
+    /// Returns the format of `path`, falling back to YAML for unknown extensions
+    pub fn detect(path: &Path, format: Option<ConfigFormat>) -> Self {
+        format
+            .or_else(|| ConfigFormat::from_path(path))
+            .unwrap_or_default()
+    }
+
+    /// Deserializes `contents`, reporting keys the target type ignored
+    ///
+    /// # Arguments
+    /// * `contents` - Text in this format
+    /// * `ignored` - Called with the path of every key that was not recognized
+    ///
+    /// # Returns
+    /// The deserialized value, or the parser's error message
+    pub fn deserialize<T, F>(self, contents: &str, ignored: F) -> Result<T, String>
+    where
+        T: DeserializeOwned,
+        F: FnMut(serde_ignored::Path),
+    {
+        match self {
+            ConfigFormat::Yaml => {
+                let deserializer = serde_yaml::Deserializer::from_str(contents);
+                serde_ignored::deserialize(deserializer, ignored).map_err(|e| e.to_string())
+            }
+            ConfigFormat::Toml => {
+                let deserializer = toml::Deserializer::new(contents);
+                serde_ignored::deserialize(deserializer, ignored).map_err(|e| e.to_string())
+            }
+            ConfigFormat::Json => {
+                let mut deserializer = serde_json::Deserializer::from_str(contents);
+                let value = serde_ignored::deserialize(&mut deserializer, ignored)
+                    .map_err(|e| e.to_string())?;
+                deserializer.end().map_err(|e| e.to_string())?;
+                Ok(value)
+            }
+        }
+    }
+
+    /// Serializes `value` in this format
+    pub fn serialize<T: serde::Serialize>(self, value: &T) -> Result<String, String> {
+        match self {
+            ConfigFormat::Yaml => serde_yaml::to_string(value).map_err(|e| e.to_string()),
+            ConfigFormat::Toml => toml::to_string_pretty(value).map_err(|e| e.to_string()),
+            ConfigFormat::Json => serde_json::to_string_pretty(value)
+                .map(|json| json + "\n")
+                .map_err(|e| e.to_string()),
+        }
+    }
+}
+
+impl FromStr for ConfigFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "yaml" | "yml" => Ok(ConfigFormat::Yaml),
+            "toml" => Ok(ConfigFormat::Toml),
+            "json" => Ok(ConfigFormat::Json),
+            _ => Err(format!(
+                "Unknown config format '{}' (expected one of: {})",
+                s,
+                ConfigFormat::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for ConfigFormat {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ConfigFormat::Yaml => write!(f, "yaml"),
+            ConfigFormat::Toml => write!(f, "toml"),
+            ConfigFormat::Json => write!(f, "json"),
+        }
+    }
+}
+
+/// Translates a configuration between formats, keeping the keys as written
+///
+/// # Arguments
+/// * `contents` - Configuration text
+/// * `path` - Path the text was read from, used in error messages
+/// * `from` - Format of `contents`
+/// * `to` - Format to translate to
+///
+/// # Returns
+/// The configuration in the target format
+pub fn convert(
+    contents: &str,
+    path: &str,
+    from: ConfigFormat,
+    to: ConfigFormat,
+) -> Result<String, DeployError> {
+    // An order-preserving value keeps the keys in the order they were written
+    let value: serde_yaml::Value =
+        from.deserialize(contents, |_| {})
+            .map_err(|message| DeployError::ParseError {
+                path: path.to_string(),
+                message,
+            })?;
+
+    // TOML has no null, so unset keys are left out rather than failing
+    let value = match to {
+        ConfigFormat::Toml => strip_nulls(value),
+        _ => value,
+    };
+
+    to.serialize(&value).map_err(|e| DeployError::ParseError {
+        path: path.to_string(),
+        message: format!("cannot be written as {}: {}", to, e),
+    })
+}
+
+/// Removes null mapping entries and sequence items, recursively
+fn strip_nulls(value: serde_yaml::Value) -> serde_yaml::Value {
+    use serde_yaml::Value;
+
+    match value {
+        Value::Mapping(mapping) => Value::Mapping(
+            mapping
+                .into_iter()
+                .filter(|(_, value)| !value.is_null())
+                .map(|(key, value)| (key, strip_nulls(value)))
+                .collect(),
+        ),
+        Value::Sequence(items) => Value::Sequence(
+            items
+                .into_iter()
+                .filter(|item| !item.is_null())
+                .map(strip_nulls)
+                .collect(),
+        ),
+        Value::Tagged(tagged) => strip_nulls(tagged.value),
+        other => other,
+    }
+}
@@ -3,7 +3,20 @@ use std::path::{Path, PathBuf};
 use std::process::{Command, Stdio};
 use uuid::Uuid;
 
-use crate::config::Resource;
+use crate::condition::{self, Context};
+use crate::config::{OverwritePolicy, Resource, ResourcesDir};
+use crate::error::DeployError;
+use crate::manifest::{self, Manifest};
+use crate::resources::{self, Lockfile};
+use crate::secrets;
+
+/// Number of characters commits are abbreviated to in messages
+const SHORT_SHA_LEN: usize = 7;
+
+/// Abbreviates a commit hash for messages and notes
+pub fn short_sha(sha: &str) -> String {
+    sha.chars().take(SHORT_SHA_LEN).collect()
+}
 
 /// Clones/updates a git repository and copies resources into it
 ///
@@ -15,9 +28,12 @@ use crate::config::Resource;
 /// * `merge` - If true, fetches and merges latest changes from upstream
 /// * `verbose` - Enable verbose logging
 /// * `resources` - List of resources to copy into the cloned repository
+/// * `resources_dir` - Resource directories, searched in order
+/// * `context` - Values that resource `when:` conditions are evaluated against
 ///
 /// # Returns
 /// Path to the cloned repository on success
+#[allow(clippy::too_many_arguments)]
 pub fn checkout_repository(
     config_path: &str,
     repo_url: &str,
@@ -26,7 +42,40 @@ pub fn checkout_repository(
     merge: bool,
     verbose: bool,
     resources: &[Resource],
-) -> Result<PathBuf, Box<dyn std::error::Error>> {
+    resources_dir: &ResourcesDir,
+    context: &Context,
+) -> Result<PathBuf, DeployError> {
+    let target_path = prepare_repository(repo_url, branch, clean, merge, verbose)?;
+    copy_release_resources(
+        config_path,
+        &target_path,
+        clean,
+        resources,
+        resources_dir,
+        context,
+        verbose,
+    )?;
+    Ok(target_path)
+}
+
+/// Clones or updates a git repository, without copying resources
+///
+/// # Arguments
+/// * `repo_url` - Git repository URL to clone
+/// * `branch` - Branch name to checkout
+/// * `clean` - If true, creates a new directory; if false, uses current directory
+/// * `merge` - If true, fetches and merges latest changes from upstream
+/// * `verbose` - Enable verbose logging
+///
+/// # Returns
+/// Path to the checkout
+pub fn prepare_repository(
+    repo_url: &str,
+    branch: &str,
+    clean: bool,
+    merge: bool,
+    verbose: bool,
+) -> Result<PathBuf, DeployError> {
     let target_path = determine_target_path(clean)?;
 
     if clean {
@@ -53,13 +102,48 @@ pub fn checkout_repository(
         update_repository(branch, &target_path, verbose)?;
     }
 
-    copy_resources(config_path, &target_path, resources, verbose)?;
-
     Ok(target_path)
 }
 
+/// Copies resources into a checkout prepared by [`prepare_repository`]
+///
+/// # Arguments
+/// * `config_path` - Path to the configuration file (used to locate resources)
+/// * `target_path` - Checkout to copy into
+/// * `clean` - Whether the checkout is a clean one, which needs no cleanup later
+/// * `resources` - List of resources to copy
+/// * `resources_dir` - Resource directories, searched in order
+/// * `context` - Values that resource `when:` conditions are evaluated against
+/// * `verbose` - Enable verbose logging
+pub fn copy_release_resources(
+    config_path: &str,
+    target_path: &Path,
+    clean: bool,
+    resources: &[Resource],
+    resources_dir: &ResourcesDir,
+    context: &Context,
+    verbose: bool,
+) -> Result<(), DeployError> {
+    // Track written files in non-clean mode so they can be cleaned up later
+    let mut manifest = if clean {
+        None
+    } else {
+        Some(Manifest::load(target_path)?)
+    };
+
+    copy_resources(
+        config_path,
+        target_path,
+        resources,
+        resources_dir,
+        context,
+        manifest.as_mut(),
+        verbose,
+    )
+}
+
 /// Determines where to clone the repository based on clean flag
-fn determine_target_path(clean: bool) -> Result<PathBuf, Box<dyn std::error::Error>> {
+fn determine_target_path(clean: bool) -> Result<PathBuf, DeployError> {
     if clean {
         // Create a UUID-named directory in the current working directory
         let uuid = Uuid::new_v4();
@@ -77,7 +161,7 @@ fn clone_repository(
     branch: &str,
     target_path: &Path,
     verbose: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), DeployError> {
     if verbose {
         println!(
             "Cloning repository {} branch {} to {}",
@@ -100,43 +184,443 @@ fn clone_repository(
         .status()?;
 
     if !status.success() {
-        return Err(format!(
-            "Git clone failed with exit code: {}",
-            status.code().unwrap_or(1)
-        )
-        .into());
+        return Err(DeployError::CloneFailed {
+            code: status.code().unwrap_or(1),
+        });
     }
 
     Ok(())
 }
 
-/// Updates an existing repository by fetching and merging from upstream
-fn update_repository(
+/// Checks out a single revision of a repository without its history
+///
+/// # Arguments
+/// * `repo_url` - Git repository URL
+/// * `reference` - Branch, tag or commit to check out
+/// * `target_path` - Directory to check the revision out into
+/// * `verbose` - Enable verbose logging
+pub fn fetch_snapshot(
+    repo_url: &str,
+    reference: &str,
+    target_path: &Path,
+    verbose: bool,
+) -> Result<(), DeployError> {
+    if verbose {
+        println!(
+            "Fetching {} at {} into {}",
+            repo_url,
+            reference,
+            target_path.display()
+        );
+    }
+
+    fs::create_dir_all(target_path)?;
+    run_git(target_path, &["init", "--quiet"])?;
+    run_git(
+        target_path,
+        &["fetch", "--quiet", "--depth", "1", repo_url, reference],
+    )?;
+    run_git(target_path, &["checkout", "--quiet", "FETCH_HEAD"])?;
+
+    Ok(())
+}
+
+/// A commit as listed in a deployment's change summary
+#[derive(Debug, Clone)]
+pub struct CommitInfo {
+    /// Full commit SHA
+    pub sha: String,
+    /// Author name
+    pub author: String,
+    /// First line of the commit message
+    pub subject: String,
+}
+
+/// Returns the commit checked out in a repository
+pub fn head_commit(repo_path: &Path) -> Result<String, DeployError> {
+    Ok(run_git(repo_path, &["rev-parse", "HEAD"])?
+        .trim()
+        .to_string())
+}
+
+/// Returns whether a repository contains a commit
+pub fn has_commit(repo_path: &Path, sha: &str) -> bool {
+    run_git(
+        repo_path,
+        &["cat-file", "-e", &format!("{}^{{commit}}", sha)],
+    )
+    .is_ok()
+}
+
+/// Lists the commits reachable from `to` but not from `from`, newest first
+///
+/// # Arguments
+/// * `repo_path` - Repository to read the history of
+/// * `from` - Commit the range starts after, or `None` for the whole history
+/// * `to` - Commit the range ends at
+pub fn commits_between(
+    repo_path: &Path,
+    from: Option<&str>,
+    to: &str,
+) -> Result<Vec<CommitInfo>, DeployError> {
+    let range = match from {
+        Some(from) => format!("{}..{}", from, to),
+        None => to.to_string(),
+    };
+    let log = run_git(repo_path, &["log", "--format=%H%x1f%an%x1f%s", &range])?;
+
+    Ok(log
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(3, '\x1f');
+            Some(CommitInfo {
+                sha: fields.next()?.to_string(),
+                author: fields.next()?.to_string(),
+                subject: fields.next().unwrap_or_default().to_string(),
+            })
+        })
+        .collect())
+}
+
+/// Returns `git diff --stat` output between two commits
+pub fn diff_stat(repo_path: &Path, from: &str, to: &str) -> Result<String, DeployError> {
+    run_git(repo_path, &["diff", "--stat", from, to])
+}
+
+/// Lists the tags matching a pattern that are reachable from `HEAD`
+pub fn merged_tags(repo_path: &Path, pattern: &str) -> Result<Vec<String>, DeployError> {
+    let tags = run_git(repo_path, &["tag", "--list", "--merged", "HEAD", pattern])?;
+    Ok(tags.lines().map(String::from).collect())
+}
+
+/// Returns the most recent tag reachable from a commit, if any
+pub fn latest_tag(repo_path: &Path, commit: &str) -> Option<String> {
+    run_git(repo_path, &["describe", "--tags", "--abbrev=0", commit])
+        .ok()
+        .map(|tag| tag.trim().to_string())
+}
+
+/// Creates an annotated tag on the commit checked out in a repository
+///
+/// # Arguments
+/// * `repo_path` - Repository to tag
+/// * `name` - Tag name
+/// * `message` - Tag message
+pub fn create_tag(repo_path: &Path, name: &str, message: &str) -> Result<(), DeployError> {
+    // Verbatim, as Markdown headings would otherwise be stripped as comments
+    run_git(
+        repo_path,
+        &[
+            "tag",
+            "--annotate",
+            "--cleanup=verbatim",
+            "--message",
+            message,
+            "--",
+            name,
+        ],
+    )
+    .map(|_| ())
+}
+
+/// Commits a single file in a repository
+///
+/// # Arguments
+/// * `repo_path` - Repository to commit in
+/// * `path` - File to commit, relative to the repository root
+/// * `message` - Commit message
+pub fn commit_file(repo_path: &Path, path: &str, message: &str) -> Result<(), DeployError> {
+    run_git(repo_path, &["add", "--", path])?;
+    run_git(
+        repo_path,
+        &["commit", "--quiet", "--message", message, "--", path],
+    )
+    .map(|_| ())
+}
+
+/// Returns the tags pointing to a commit
+pub fn tags_at(repo_path: &Path, commit: &str) -> Result<Vec<String>, DeployError> {
+    let output = run_git(repo_path, &["tag", "--points-at", commit])?;
+    Ok(output.lines().map(String::from).collect())
+}
+
+/// Checks the signature of a commit or tag, reporting it in git's raw format
+///
+/// SSH signatures are checked against an empty allowed signers file, so
+/// git reports the key of any good signature and trust is left to the
+/// caller. GPG signatures need the public key in the keyring.
+///
+/// # Arguments
+/// * `repo_path` - Repository containing the object
+/// * `kind` - `commit` or `tag`
+/// * `object` - Commit or tag to check
+///
+/// # Returns
+/// The output of `git verify-commit --raw` or `git verify-tag --raw`, which
+/// is empty for unsigned objects
+pub fn signature_status(repo_path: &Path, kind: &str, object: &str) -> Result<String, DeployError> {
+    let output = Command::new("git")
+        .args(["-c", "gpg.ssh.allowedSignersFile=/dev/null"])
+        .arg(format!("verify-{}", kind))
+        .args(["--raw", object])
+        .current_dir(repo_path)
+        .stdin(Stdio::null())
+        .output()?;
+
+    // The exit code only says whether git trusts the key, which is decided by the caller
+    let mut status = String::from_utf8_lossy(&output.stderr).into_owned();
+    status.push_str(&String::from_utf8_lossy(&output.stdout));
+    Ok(status)
+}
+
+/// Returns the commit `origin/<branch>` pointed to when it was last fetched
+///
+/// # Returns
+/// The commit SHA, or `None` if the checkout has no such remote-tracking branch
+pub fn tracking_commit(repo_path: &Path, branch: &str) -> Result<Option<String>, DeployError> {
+    let tracking = format!("refs/remotes/origin/{}", branch);
+    Ok(
+        run_git(repo_path, &["rev-parse", "--quiet", "--verify", &tracking])
+            .ok()
+            .map(|sha| sha.trim().to_string()),
+    )
+}
+
+/// Pushes the checkout's `HEAD` and tags to `origin`
+///
+/// The push is refused if the remote branch no longer points to the commit
+/// it pointed to at checkout. Without `force_with_lease`, git itself refuses
+/// to push anything but a fast-forward; with it, the branch may be
+/// overwritten, and the lease keeps a concurrent push from being lost.
+///
+/// # Arguments
+/// * `repo_path` - Checkout being deployed
+/// * `branch` - Branch to push `HEAD` to
+/// * `tags` - Tags to push along with the branch
+/// * `upstream` - Commit the remote branch pointed to at checkout
+/// * `force_with_lease` - Whether the branch may be overwritten
+/// * `verbose` - Whether to print progress
+pub fn push(
+    repo_path: &Path,
     branch: &str,
+    tags: &[String],
+    upstream: Option<&str>,
+    force_with_lease: bool,
+    verbose: bool,
+) -> Result<(), DeployError> {
+    ensure_on_branch(repo_path, branch, "Not pushing")?;
+    let branch_ref = format!("refs/heads/{}", branch);
+    let listing = run_git(repo_path, &["ls-remote", "origin", &branch_ref])?;
+    let current = listing
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .find(|(_, name)| *name == branch_ref)
+        .map(|(sha, _)| sha);
+    if current != upstream {
+        let short = |sha: Option<&str>| sha.map_or("nothing".to_string(), short_sha);
+        return Err(DeployError::Git(format!(
+            "Not pushing: origin/{} moved from {} to {} since the checkout",
+            branch,
+            short(upstream),
+            short(current)
+        )));
+    }
+
+    let mut args = vec!["push".to_string(), "--quiet".to_string()];
+    if !tags.is_empty() {
+        // The branch and its tags are updated together or not at all
+        args.push("--atomic".to_string());
+    }
+    if force_with_lease {
+        args.push(format!(
+            "--force-with-lease={}:{}",
+            branch_ref,
+            upstream.unwrap_or_default()
+        ));
+    }
+    args.push("origin".to_string());
+    args.push(format!("HEAD:{}", branch_ref));
+    args.extend(tags.iter().map(|tag| format!("refs/tags/{}", tag)));
+
+    if verbose {
+        println!("Pushing {} to origin...", branch);
+    }
+    let args: Vec<&str> = args.iter().map(String::as_str).collect();
+    run_git(repo_path, &args).map(|_| ())
+}
+
+/// Merges the deployed branch into another branch and pushes the result
+///
+/// The merge is done in a temporary worktree on the target's upstream state,
+/// so the checkout itself is left as it is. A conflicting merge is aborted
+/// and nothing is pushed.
+///
+/// # Arguments
+/// * `repo_path` - Checkout being deployed
+/// * `branch` - Branch being deployed, merged at the checkout's `HEAD`
+/// * `target` - Branch on `origin` to merge into
+/// * `verbose` - Whether to print progress
+pub fn merge_back(
     repo_path: &Path,
+    branch: &str,
+    target: &str,
     verbose: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
+) -> Result<(), DeployError> {
+    ensure_on_branch(repo_path, branch, "Not merging back")?;
     if verbose {
-        println!(
-            "Updating repository in {} (branch: {})",
-            repo_path.display(),
+        println!("Fetching origin/{}...", target);
+    }
+    run_git(repo_path, &["fetch", "--quiet", "origin", target])?;
+    let upstream = run_git(repo_path, &["rev-parse", "FETCH_HEAD"])?;
+    let head = run_git(repo_path, &["rev-parse", "HEAD"])?;
+
+    let worktree = std::env::temp_dir().join(format!("ud-merge-{}", Uuid::new_v4()));
+    let worktree_arg = worktree.to_string_lossy().into_owned();
+    run_git(
+        repo_path,
+        &[
+            "worktree",
+            "add",
+            "--quiet",
+            "--detach",
+            &worktree_arg,
+            upstream.trim(),
+        ],
+    )?;
+
+    let result = merge_in_worktree(&worktree, head.trim(), branch, target, verbose);
+
+    // The worktree is removed whether or not the merge went through
+    if run_git(repo_path, &["worktree", "remove", "--force", &worktree_arg]).is_err() {
+        let _ = fs::remove_dir_all(&worktree);
+        let _ = run_git(repo_path, &["worktree", "prune"]);
+    }
+    result
+}
+
+/// Fails unless the checkout's `HEAD` is the local `branch`
+///
+/// In non-clean mode the current directory may have another branch checked
+/// out, which must not be pushed or merged in place of the release branch.
+///
+/// # Arguments
+/// * `repo_path` - Checkout being deployed
+/// * `branch` - Branch being deployed
+/// * `action` - What is refused otherwise, e.g. `Not pushing`
+fn ensure_on_branch(repo_path: &Path, branch: &str, action: &str) -> Result<(), DeployError> {
+    let head = run_git(repo_path, &["symbolic-ref", "--quiet", "HEAD"]).ok();
+    match head.as_deref().map(str::trim) {
+        Some(head) if head.strip_prefix("refs/heads/") == Some(branch) => Ok(()),
+        Some(head) => Err(DeployError::Git(format!(
+            "{}: the checkout is on '{}', not on '{}'",
+            action,
+            head.strip_prefix("refs/heads/").unwrap_or(head),
             branch
-        );
+        ))),
+        None => Err(DeployError::Git(format!(
+            "{}: HEAD is detached, not on '{}'",
+            action, branch
+        ))),
     }
+}
 
-    // Check for uncommitted changes
-    let status_output = Command::new("git")
-        .arg("status")
-        .arg("--porcelain")
+/// Merges a commit into the detached `HEAD` of a worktree and pushes it to `target`
+fn merge_in_worktree(
+    worktree: &Path,
+    commit: &str,
+    branch: &str,
+    target: &str,
+    verbose: bool,
+) -> Result<(), DeployError> {
+    if verbose {
+        println!("Merging {} into {}...", branch, target);
+    }
+    let message = format!("Merge branch '{}' into {}", branch, target);
+    let output = Command::new("git")
+        .args([
+            "merge",
+            "--no-ff",
+            "--no-edit",
+            "--message",
+            &message,
+            commit,
+        ])
+        .current_dir(worktree)
+        .stdin(Stdio::null())
+        .output()?;
+
+    if !output.status.success() {
+        let merging = run_git(
+            worktree,
+            &["rev-parse", "--quiet", "--verify", "MERGE_HEAD"],
+        )
+        .is_ok();
+        if !merging {
+            return Err(DeployError::Git(format!(
+                "git merge failed: {}",
+                String::from_utf8_lossy(&output.stderr).trim()
+            )));
+        }
+        run_git(worktree, &["merge", "--abort"])?;
+        return Err(DeployError::MergeConflict {
+            branch: format!("{} into {}", branch, target),
+            code: output.status.code().unwrap_or(1),
+        });
+    }
+
+    if verbose {
+        println!("Pushing {} to origin...", target);
+    }
+    run_git(
+        worktree,
+        &[
+            "push",
+            "--quiet",
+            "origin",
+            &format!("HEAD:refs/heads/{}", target),
+        ],
+    )
+    .map(|_| ())
+}
+
+/// Runs a git command in `repo_path`, failing with its error output
+///
+/// # Returns
+/// The command's standard output
+fn run_git(repo_path: &Path, args: &[&str]) -> Result<String, DeployError> {
+    let output = Command::new("git")
+        .args(args)
         .current_dir(repo_path)
+        .stdin(Stdio::null())
         .output()?;
 
-    if !status_output.stdout.is_empty() {
-        return Err(
-            "Repository has uncommitted changes. Commit or stash changes before updating.".into(),
+    if !output.status.success() {
+        return Err(DeployError::Git(format!(
+            "git {} failed: {}",
+            args[0],
+            String::from_utf8_lossy(&output.stderr).trim()
+        )));
+    }
+
+    Ok(String::from_utf8_lossy(&output.stdout).into_owned())
+}
+
+/// Updates an existing repository by fetching and merging from upstream
+fn update_repository(branch: &str, repo_path: &Path, verbose: bool) -> Result<(), DeployError> {
+    if verbose {
+        println!(
+            "Updating repository in {} (branch: {})",
+            repo_path.display(),
+            branch
         );
     }
 
+    // Check for uncommitted changes, ignoring files placed by ud itself
+    let changed = uncommitted_changes(repo_path)?;
+    if !changed.is_empty() {
+        return Err(DeployError::DirtyWorktree(changed));
+    }
+
     // Fetch latest changes
     if verbose {
         println!("Fetching latest changes from origin...");
@@ -154,11 +638,10 @@ fn update_repository(
         .status()?;
 
     if !fetch_status.success() {
-        return Err(format!(
+        return Err(DeployError::Git(format!(
             "Git fetch failed with exit code: {}",
             fetch_status.code().unwrap_or(1)
-        )
-        .into());
+        )));
     }
 
     // Merge changes
@@ -176,11 +659,10 @@ fn update_repository(
         .status()?;
 
     if !merge_status.success() {
-        return Err(format!(
-            "Git merge failed with exit code: {}",
-            merge_status.code().unwrap_or(1)
-        )
-        .into());
+        return Err(DeployError::MergeConflict {
+            branch: format!("origin/{}", branch),
+            code: merge_status.code().unwrap_or(1),
+        });
     }
 
     if verbose {
@@ -190,82 +672,262 @@ fn update_repository(
     Ok(())
 }
 
+/// Lists paths with uncommitted changes, excluding files recorded in the manifest
+fn uncommitted_changes(repo_path: &Path) -> Result<Vec<String>, DeployError> {
+    let output = Command::new("git")
+        .arg("status")
+        .arg("--porcelain=v1")
+        .arg("-z")
+        .arg("--untracked-files=all")
+        .current_dir(repo_path)
+        .output()?;
+
+    if !output.status.success() {
+        return Err(DeployError::Git(format!(
+            "Git status failed with exit code: {}",
+            output.status.code().unwrap_or(1)
+        )));
+    }
+
+    let manifest = Manifest::load(repo_path)?;
+    let placed = manifest.paths();
+    let state_dir = format!("{}/", manifest::STATE_DIR);
+
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let mut entries = stdout.split('\0').filter(|entry| !entry.is_empty());
+    let mut changed = Vec::new();
+
+    while let Some(entry) = entries.next() {
+        let (status, path) = entry.split_at(3.min(entry.len()));
+        // Renames and copies are followed by the original path
+        if status.starts_with('R') || status.starts_with('C') {
+            entries.next();
+        }
+        if !placed.contains(path) && !path.starts_with(&state_dir) {
+            changed.push(path.to_string());
+        }
+    }
+
+    Ok(changed)
+}
+
 /// Copies resources from config directory to target repository
+///
+/// When a manifest is given, every written file is recorded in it.
 fn copy_resources(
     config_path: &str,
     target_path: &Path,
     resources: &[Resource],
+    resources_dir: &ResourcesDir,
+    context: &Context,
+    mut manifest: Option<&mut Manifest>,
     verbose: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let config_dir = Path::new(config_path)
-        .parent()
-        .unwrap_or_else(|| Path::new("."));
+) -> Result<(), DeployError> {
+    let roots = resources::resource_roots(config_path, resources_dir);
+
+    // Conditions may check for files, so evaluate them against the checkout
+    let context = Context {
+        repo_path: Some(target_path),
+        ..*context
+    };
 
+    let mut selected = Vec::new();
     for resource in resources {
-        copy_single_resource(config_dir, target_path, resource, verbose)?;
+        match &resource.when {
+            Some(when) if !condition::evaluate(when, &context)? => {
+                if verbose {
+                    println!("Skipping resource {} (when: {})", resource.file, when);
+                }
+            }
+            _ => selected.push(resource),
+        }
+    }
+
+    let lockfile = Lockfile::load(config_path)?;
+
+    if verbose && lockfile.is_some() {
+        println!(
+            "Verifying resources against {}",
+            resources::lockfile_path(config_path).display()
+        );
+    }
+
+    // Only require a key when something actually needs decrypting
+    let key = if selected.iter().any(|r| r.encrypted) {
+        Some(secrets::load_key(None)?)
+    } else {
+        None
+    };
+
+    let mut replaced = Vec::new();
+    for resource in selected {
+        let outcome = copy_single_resource(
+            &roots,
+            target_path,
+            resource,
+            lockfile.as_ref(),
+            key.as_ref(),
+            verbose,
+        )?;
+
+        if let Some(manifest) = manifest.as_deref_mut() {
+            manifest.record(
+                target_path,
+                resource.destination(),
+                outcome.previous.as_deref(),
+            )?;
+        }
+
+        if let Some(summary) = outcome.replaced {
+            replaced.push((resource.destination(), summary));
+        }
+    }
+
+    if !replaced.is_empty() {
+        println!("Resources replaced existing content:");
+        for (destination, summary) in replaced {
+            if verbose {
+                println!("  {} ({})", destination, summary);
+            } else {
+                println!("  {}", destination);
+            }
+        }
     }
 
     Ok(())
 }
 
-/// Copies a single resource file
+/// Outcome of copying a single resource
+struct CopyOutcome {
+    /// Content of the destination before it was written, if it existed
+    previous: Option<Vec<u8>>,
+    /// Summary of the changes if existing content was replaced
+    replaced: Option<String>,
+}
+
+/// Copies a single resource file after verifying its checksum
+///
+/// Encrypted resources are decrypted in memory and written with
+/// permissions restricted to the current user.
 fn copy_single_resource(
-    config_dir: &Path,
+    roots: &[PathBuf],
     target_path: &Path,
     resource: &Resource,
+    lockfile: Option<&Lockfile>,
+    key: Option<&secrets::Key>,
     verbose: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let source_path = config_dir.join("resources").join(&resource.file);
-    let dest_path = target_path.join(resource.copy_path.as_ref().unwrap_or(&resource.file));
-
-    // Validate paths to prevent traversal attacks
-    validate_path(&source_path, &config_dir.join("resources"))?;
-    validate_path(&dest_path, target_path)?;
+) -> Result<CopyOutcome, DeployError> {
+    let source = resources::resolve_source(roots, resource)?;
+    let source_path = source.path;
+    let dest_path = target_path.join(resource.destination());
+
+    // Validate the destination to prevent traversal attacks
+    resources::validate_path(&dest_path, target_path)?;
+
+    // Read once so the verified bytes are exactly the ones written
+    let contents = resources::read_resource(&source_path)?;
+    resources::verify_checksum(resource, &source_path, &contents, lockfile)?;
+
+    let output = if resource.encrypted {
+        let key = key.expect("Key is loaded when encrypted resources exist");
+        secrets::decrypt(key, &contents).map_err(|e| {
+            DeployError::Secrets(format!(
+                "Could not decrypt resource '{}': {}",
+                resource.file, e
+            ))
+        })?
+    } else {
+        contents
+    };
+
+    let existing = if dest_path.is_file() {
+        check_overwrite(target_path, resource)?;
+        Some(fs::read(&dest_path).map_err(|e| {
+            DeployError::Resource(format!("Could not read {}: {}", dest_path.display(), e))
+        })?)
+    } else {
+        None
+    };
 
     if verbose {
         println!(
-            "Copying resource: {} -> {}",
+            "{} resource: {} -> {} (from {})",
+            if resource.encrypted {
+                "Decrypting"
+            } else {
+                "Copying"
+            },
             source_path.display(),
-            dest_path.display()
+            dest_path.display(),
+            source.root.display()
         );
     }
 
     // Create destination directory if it doesn't exist
+    let write_failed = |e: std::io::Error| {
+        DeployError::Resource(format!("Could not write {}: {}", dest_path.display(), e))
+    };
     if let Some(parent) = dest_path.parent() {
-        fs::create_dir_all(parent)?;
+        fs::create_dir_all(parent).map_err(write_failed)?;
     }
 
-    fs::copy(&source_path, &dest_path)?;
+    if resource.encrypted {
+        secrets::write_private(&dest_path, &output)?;
+    } else {
+        fs::write(&dest_path, &output).map_err(write_failed)?;
+        fs::metadata(&source_path)
+            .and_then(|metadata| fs::set_permissions(&dest_path, metadata.permissions()))
+            .map_err(write_failed)?;
+    }
 
-    Ok(())
+    let replaced = existing
+        .as_ref()
+        .filter(|existing| **existing != output)
+        .map(|existing| resources::diff_summary(existing, &output));
+
+    Ok(CopyOutcome {
+        previous: existing,
+        replaced,
+    })
 }
 
-/// Validates that a path doesn't escape its intended base directory
-fn validate_path(path: &Path, base: &Path) -> Result<(), Box<dyn std::error::Error>> {
-    let canonical_path = path.canonicalize().or_else(|_| {
-        // If path doesn't exist yet, validate parent
-        if let Some(parent) = path.parent() {
-            parent.canonicalize()
-        } else {
-            Err(std::io::Error::new(
-                std::io::ErrorKind::NotFound,
-                "Invalid path",
-            ))
+/// Checks whether a resource may replace the existing file at its destination
+fn check_overwrite(target_path: &Path, resource: &Resource) -> Result<(), DeployError> {
+    match resource.overwrite {
+        OverwritePolicy::Always => Ok(()),
+        OverwritePolicy::Never => Err(DeployError::Resource(format!(
+            "Resource '{}' would overwrite existing file {} (overwrite: never)",
+            resource.file,
+            resource.destination()
+        ))),
+        OverwritePolicy::Untracked => {
+            if is_tracked(target_path, resource.destination())? {
+                Err(DeployError::Resource(format!(
+                    "Resource '{}' would overwrite {}, which is tracked in git. \
+                     Set `overwrite: always` on the resource to allow this.",
+                    resource.file,
+                    resource.destination()
+                )))
+            } else {
+                Ok(())
+            }
         }
-    })?;
-
-    let canonical_base = base.canonicalize()?;
-
-    if !canonical_path.starts_with(&canonical_base) {
-        return Err(format!(
-            "Path traversal attempt detected: {} is outside {}",
-            path.display(),
-            base.display()
-        )
-        .into());
     }
+}
 
-    Ok(())
+/// Checks whether a path is tracked in the repository's index
+fn is_tracked(repo_path: &Path, path: &str) -> Result<bool, DeployError> {
+    let status = Command::new("git")
+        .arg("ls-files")
+        .arg("--error-unmatch")
+        .arg("--")
+        .arg(path)
+        .current_dir(repo_path)
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()?;
+
+    Ok(status.success())
 }
 
 /// Executes a deployment tool in the repository directory
@@ -283,7 +945,28 @@ pub fn execute_tool(
     arguments: &[String],
     repo_path: &Path,
     verbose: bool,
-) -> Result<i32, Box<dyn std::error::Error>> {
+) -> Result<i32, DeployError> {
+    execute_tool_with_env(tool_name, arguments, &[], repo_path, verbose)
+}
+
+/// Executes a deployment tool with extra environment variables
+///
+/// # Arguments
+/// * `tool_name` - Name or path of the tool to execute
+/// * `arguments` - Arguments to pass to the tool
+/// * `env` - Environment variables set for the tool, in addition to the inherited ones
+/// * `repo_path` - Path to the repository where the tool should run
+/// * `verbose` - Enable verbose logging
+///
+/// # Returns
+/// Exit code of the tool execution
+pub fn execute_tool_with_env(
+    tool_name: &str,
+    arguments: &[String],
+    env: &[(&str, &str)],
+    repo_path: &Path,
+    verbose: bool,
+) -> Result<i32, DeployError> {
     if tool_name.is_empty() {
         return Ok(0); // Nothing to execute
     }
@@ -307,6 +990,7 @@ pub fn execute_tool(
 
     let status = Command::new(tool_name)
         .args(arguments)
+        .envs(env.iter().copied())
         .current_dir(repo_path)
         .stdin(Stdio::inherit())
         .stdout(Stdio::inherit())
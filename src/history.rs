@@ -0,0 +1,154 @@
+use serde::{Deserialize, Serialize};
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::error::DeployError;
+use crate::manifest::{self, STATE_DIR};
+
+/// File name of the history inside the state directory
+const HISTORY_FILE: &str = "history.yml";
+
+/// Held while the history is read and rewritten, as releases running in
+/// parallel append to the same file; other processes are kept out by the
+/// deployment lock
+static APPEND: Mutex<()> = Mutex::new(());
+
+/// Deployments run from a working directory, oldest first
+///
+/// The history is kept in `.ud/history.yml` in the working directory, so it
+/// survives clean checkouts being removed.
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct History {
+    /// Recorded deployments
+    #[serde(default)]
+    pub deployments: Vec<HistoryEntry>,
+}
+
+/// A recorded deployment
+#[derive(Debug, Clone, Deserialize, Serialize)]
+pub struct HistoryEntry {
+    /// Seconds since the Unix epoch when the deployment finished
+    pub time: u64,
+
+    /// Name of the release under `releases`, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub release: Option<String>,
+
+    /// Environment selected with `--env`, if any
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub env: Option<String>,
+
+    /// Repository URL
+    pub repository: String,
+
+    /// Branch that was checked out
+    pub branch: String,
+
+    /// Commit that was deployed
+    pub commit: String,
+
+    /// Whether the deployment succeeded
+    pub succeeded: bool,
+
+    /// Whether branch policies were overridden with `--override-policy`
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub policy_overridden: bool,
+}
+
+impl HistoryEntry {
+    /// Creates an entry stamped with the current time
+    pub fn now(
+        release: Option<&str>,
+        env: Option<&str>,
+        repository: &str,
+        branch: &str,
+        commit: &str,
+        succeeded: bool,
+    ) -> Self {
+        HistoryEntry {
+            time: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map_or(0, |elapsed| elapsed.as_secs()),
+            release: release.map(String::from),
+            env: env.map(String::from),
+            repository: repository.to_string(),
+            branch: branch.to_string(),
+            commit: commit.to_string(),
+            succeeded,
+            policy_overridden: false,
+        }
+    }
+}
+
+impl History {
+    /// Loads the history of a working directory, or an empty one if none exists
+    pub fn load(dir: &Path) -> Result<Self, DeployError> {
+        let path = history_path(dir);
+        if !path.exists() {
+            return Ok(History::default());
+        }
+
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            DeployError::Resource(format!(
+                "Could not read deployment history {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        serde_yaml::from_str(&contents).map_err(|e| {
+            DeployError::Resource(format!(
+                "Failed to parse deployment history {}: {}",
+                path.display(),
+                e
+            ))
+        })
+    }
+
+    /// Adds an entry to the history of a working directory
+    pub fn append(dir: &Path, entry: HistoryEntry) -> Result<(), DeployError> {
+        let _append = APPEND.lock().unwrap_or_else(PoisonError::into_inner);
+        let mut history = History::load(dir)?;
+        history.deployments.push(entry);
+
+        manifest::ensure_state_dir(dir)?;
+        let yaml = serde_yaml::to_string(&history).map_err(|e| {
+            DeployError::Resource(format!("Failed to serialize deployment history: {}", e))
+        })?;
+        let path = history_path(dir);
+        fs::write(&path, yaml).map_err(|e| {
+            DeployError::Resource(format!(
+                "Could not write deployment history {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(())
+    }
+
+    /// Returns the latest successful deployment of the same release to the same environment
+    ///
+    /// # Arguments
+    /// * `release` - Name of the release under `releases`, if any
+    /// * `env` - Environment selected with `--env`, if any
+    /// * `repository` - Repository URL
+    pub fn last_success(
+        &self,
+        release: Option<&str>,
+        env: Option<&str>,
+        repository: &str,
+    ) -> Option<&HistoryEntry> {
+        self.deployments.iter().rev().find(|entry| {
+            entry.succeeded
+                && entry.release.as_deref() == release
+                && entry.env.as_deref() == env
+                && entry.repository == repository
+        })
+    }
+}
+
+/// Returns the path of the history file in a working directory
+fn history_path(dir: &Path) -> PathBuf {
+    dir.join(STATE_DIR).join(HISTORY_FILE)
+}
@@ -0,0 +1,42 @@
+//! Library behind the `ud` command line tool
+//!
+//! Loads release configurations, checks out repositories, copies resources
+//! into them and runs deployment tools. Embedders usually only need
+//! [`Config`] and [`Deployment`]:
+//!
+//! ```no_run
+//! use universal_deploy::{DeployError, DeployOptions, Deployment};
+//!
+//! match Deployment::from_path("deploy.yml", DeployOptions::default()).and_then(|d| d.run()) {
+//!     Ok(()) => {}
+//!     Err(DeployError::ToolFailed { code, .. }) => eprintln!("tool exited with {}", code),
+//!     Err(e) => eprintln!("{}", e),
+//! }
+//! ```
+
+pub mod condition;
+pub mod config;
+pub mod deployment;
+pub mod error;
+pub mod format;
+pub mod git;
+pub mod history;
+pub mod lint;
+pub mod lock;
+pub mod manifest;
+pub mod notes;
+pub mod overrides;
+pub mod resolve;
+pub mod resources;
+pub mod secrets;
+pub mod signature;
+pub mod source;
+pub mod template;
+pub mod version;
+
+pub use config::{Config, LoadOptions};
+pub use deployment::{DeployOptions, Deployment, ReleaseOutcome, ReleaseStatus};
+pub use error::DeployError;
+pub use git::{checkout_repository, execute_tool};
+pub use lock::DeployLock;
+pub use source::ConfigSource;
@@ -0,0 +1,380 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::fs;
+use std::path::{Component, Path};
+use std::process::{Command, Stdio};
+
+use yaml_rust2::parser::{Event, MarkedEventReceiver, Parser};
+use yaml_rust2::scanner::Marker;
+
+use crate::config::{Config, ConfigProblem, LoadOptions, ReleaseConfig};
+use crate::error::DeployError;
+use crate::format::ConfigFormat;
+use crate::overrides;
+use crate::resolve;
+use crate::resources::{self, Lockfile};
+
+/// A problem found while linting a configuration file
+#[derive(Debug, Clone)]
+pub struct Diagnostic {
+    /// Path of the configuration file
+    pub file: String,
+    /// 1-based line and column of the offending key, if it could be located
+    pub position: Option<(usize, usize)>,
+    /// Description of the problem
+    pub message: String,
+    /// Whether the problem is only a warning and doesn't make the file invalid
+    pub warning: bool,
+}
+
+impl fmt::Display for Diagnostic {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let severity = if self.warning { "warning: " } else { "" };
+        match self.position {
+            Some((line, column)) => write!(
+                f,
+                "{}:{}:{}: {}{}",
+                self.file, line, column, severity, self.message
+            ),
+            None => write!(f, "{}: {}{}", self.file, severity, self.message),
+        }
+    }
+}
+
+/// Lints a configuration file, collecting every problem instead of stopping at the first
+///
+/// # Arguments
+/// * `config_path` - Path to the configuration file, or `-` to read standard input
+/// * `options` - How to load the file; with `lenient`, unknown keys are warnings
+/// * `remote` - Also check that the branch or tag exists on the remote
+///
+/// # Returns
+/// All problems found, or an error if the file can't be read or parsed
+pub fn lint(
+    config_path: &str,
+    options: &LoadOptions,
+    remote: bool,
+) -> Result<Vec<Diagnostic>, DeployError> {
+    let mut resolved = resolve::resolve(config_path, options.format)?;
+    overrides::apply(&mut resolved, &options.overrides, options.override_policy)?;
+    let (config, unknown_keys) = Config::from_resolved(&resolved)?;
+
+    let mut problems = config.problems();
+
+    // An unreadable lockfile is one more problem; checksums are then checked without it
+    let lockfile = Lockfile::load(config_path).unwrap_or_else(|e| {
+        problems.push(ConfigProblem::new("lockfile", e.to_string()));
+        None
+    });
+    for (key, release) in config.release_entries() {
+        problems.extend(resource_problems(
+            config_path,
+            &key,
+            release,
+            lockfile.as_ref(),
+        ));
+        problems.extend(tool_problems(&key, release));
+
+        if remote {
+            problems.extend(remote_problems(&key, release));
+        }
+    }
+
+    let diagnostic = |problem: ConfigProblem, warning: bool| {
+        let origin = resolved.origin(&problem.path);
+        let position = origin.and_then(|origin| origin.position);
+        Diagnostic {
+            file: origin
+                .map_or(config_path, |origin| &origin.file)
+                .to_string(),
+            position,
+            message: match position {
+                Some(_) => problem.message,
+                None => format!("{}: {}", problem.path, problem.message),
+            },
+            warning,
+        }
+    };
+
+    let mut diagnostics: Vec<Diagnostic> = unknown_keys
+        .into_iter()
+        .map(|problem| diagnostic(problem, options.lenient))
+        .chain(
+            problems
+                .into_iter()
+                .map(|problem| diagnostic(problem, false)),
+        )
+        .collect();
+
+    // Report in file order, starting with the linted file and with problems
+    // that couldn't be located last
+    diagnostics.sort_by(|a, b| {
+        (a.file != resolved.path, &a.file)
+            .cmp(&(b.file != resolved.path, &b.file))
+            .then_with(|| {
+                let unlocated = (usize::MAX, usize::MAX);
+                a.position
+                    .unwrap_or(unlocated)
+                    .cmp(&b.position.unwrap_or(unlocated))
+            })
+    });
+    Ok(diagnostics)
+}
+
+/// Checks that resource sources exist, match their checksums and stay inside their directories
+fn resource_problems(
+    config_path: &str,
+    key: &str,
+    release: &ReleaseConfig,
+    lockfile: Option<&Lockfile>,
+) -> Vec<ConfigProblem> {
+    let mut problems = Vec::new();
+    let roots = resources::resource_roots(config_path, &release.resources_dir);
+
+    for (i, resource) in release.resources.iter().enumerate() {
+        let key = format!("{}.resources.{}", key, i);
+
+        // A resource that can't be read is one more problem, not the end of the lint
+        let contents = resources::resolve_source(&roots, resource).and_then(|source| {
+            resources::read_resource(&source.path).map(|contents| (source.path, contents))
+        });
+        match contents {
+            Ok((source, contents)) => {
+                let verified = resources::verify_checksum(resource, &source, &contents, lockfile);
+                if let Err(e) = verified {
+                    let field = if resource.sha256.is_some() {
+                        "sha256"
+                    } else {
+                        "file"
+                    };
+                    problems.push(ConfigProblem::new(
+                        format!("{}.{}", key, field),
+                        e.to_string(),
+                    ));
+                }
+            }
+            Err(e) => problems.push(ConfigProblem::new(format!("{}.file", key), e.to_string())),
+        }
+
+        let destination = resource.destination();
+        let escapes = Path::new(&destination)
+            .components()
+            .any(|c| !matches!(c, Component::Normal(_) | Component::CurDir));
+        if escapes {
+            let field = if resource.copy_path.is_some() {
+                "copy"
+            } else {
+                "file"
+            };
+            problems.push(ConfigProblem::new(
+                format!("{}.{}", key, field),
+                format!(
+                    "Destination '{}' must be a relative path inside the repository",
+                    destination
+                ),
+            ));
+        }
+    }
+
+    problems
+}
+
+/// Checks that the tool command can be found on `PATH`
+fn tool_problems(key: &str, release: &ReleaseConfig) -> Vec<ConfigProblem> {
+    let Some(command) = release.tool.command() else {
+        return Vec::new();
+    };
+
+    // Commands with a path are relative to the checkout, which doesn't exist yet
+    if command.contains('/') || find_on_path(command) {
+        return Vec::new();
+    }
+
+    vec![ConfigProblem::new(
+        format!("{}.tool.command", key),
+        format!("Tool command '{}' was not found on PATH", command),
+    )]
+}
+
+/// Returns whether an executable named `command` exists in a `PATH` directory
+fn find_on_path(command: &str) -> bool {
+    let Some(path) = std::env::var_os("PATH") else {
+        return false;
+    };
+
+    std::env::split_paths(&path).any(|dir| {
+        let candidate = dir.join(command);
+        match fs::metadata(&candidate) {
+            #[cfg(unix)]
+            Ok(metadata) => {
+                use std::os::unix::fs::PermissionsExt;
+                metadata.is_file() && metadata.permissions().mode() & 0o111 != 0
+            }
+            #[cfg(not(unix))]
+            Ok(metadata) => metadata.is_file(),
+            Err(_) => false,
+        }
+    })
+}
+
+/// Checks that the repository is reachable and has a branch or tag named `branch`
+fn remote_problems(key: &str, release: &ReleaseConfig) -> Vec<ConfigProblem> {
+    if release.repository.is_empty() || release.branch.is_empty() {
+        return Vec::new();
+    }
+
+    // `branch` may also name a tag, e.g. with `require_tag_pattern`
+    let status = Command::new("git")
+        .arg("ls-remote")
+        .arg("--exit-code")
+        .arg(&release.repository)
+        .arg(format!("refs/heads/{}", release.branch))
+        .arg(format!("refs/tags/{}", release.branch))
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+
+    match status {
+        Ok(status) if status.success() => Vec::new(),
+        // `--exit-code` exits with 2 when the remote has no matching ref
+        Ok(status) if status.code() == Some(2) => vec![ConfigProblem::new(
+            format!("{}.branch", key),
+            format!(
+                "No branch or tag '{}' exists on {}",
+                release.branch, release.repository
+            ),
+        )],
+        Ok(_) => vec![ConfigProblem::new(
+            format!("{}.repository", key),
+            format!("Repository {} is not reachable", release.repository),
+        )],
+        Err(e) => vec![ConfigProblem::new(
+            format!("{}.repository", key),
+            format!("Failed to execute git ls-remote: {}", e),
+        )],
+    }
+}
+
+/// Line and column of every key and sequence item in a YAML document
+pub(crate) struct KeyLocator {
+    /// Dotted key path mapped to its 1-based line and column
+    positions: HashMap<String, (usize, usize)>,
+    /// Collections currently being parsed, innermost last
+    stack: Vec<Frame>,
+}
+
+/// A YAML collection being parsed
+enum Frame {
+    Mapping {
+        path: String,
+        /// Key whose value comes next, or `None` if a key comes next
+        key: Option<String>,
+    },
+    Sequence {
+        path: String,
+        index: usize,
+    },
+}
+
+impl KeyLocator {
+    /// Records the positions of all keys in `contents`
+    ///
+    /// JSON is parsed as the YAML it is a subset of; positions in TOML files
+    /// aren't tracked. Invalid YAML yields whatever was recorded before the
+    /// error, which is fine since such files already fail to parse with their
+    /// own position.
+    pub(crate) fn locate(contents: &str, format: ConfigFormat) -> Self {
+        let mut locator = KeyLocator {
+            positions: HashMap::new(),
+            stack: Vec::new(),
+        };
+        if format != ConfigFormat::Toml {
+            let _ = Parser::new_from_str(contents).load(&mut locator, false);
+        }
+        locator
+    }
+
+    /// Returns the position of `path`, falling back to its nearest located parent
+    pub(crate) fn find(&self, path: &str) -> Option<(usize, usize)> {
+        let mut path = path;
+        loop {
+            if let Some(position) = self.positions.get(path) {
+                return Some(*position);
+            }
+            path = &path[..path.rfind('.')?];
+        }
+    }
+
+    /// Returns the path of the value starting at `mark`, recording sequence items
+    fn value_path(&mut self, mark: Marker) -> String {
+        let path = match self.stack.last() {
+            Some(Frame::Mapping { path, key }) => join(path, key.as_deref().unwrap_or_default()),
+            Some(Frame::Sequence { path, index }) => join(path, &index.to_string()),
+            None => String::new(),
+        };
+        if matches!(self.stack.last(), Some(Frame::Sequence { .. })) {
+            self.positions.insert(path.clone(), position(mark));
+        }
+        path
+    }
+
+    /// Moves the innermost collection past the value that just ended
+    fn advance(&mut self) {
+        match self.stack.last_mut() {
+            Some(Frame::Mapping { key, .. }) => *key = None,
+            Some(Frame::Sequence { index, .. }) => *index += 1,
+            None => {}
+        }
+    }
+}
+
+impl MarkedEventReceiver for KeyLocator {
+    fn on_event(&mut self, event: Event, mark: Marker) {
+        match event {
+            Event::Scalar(value, ..) => {
+                if let Some(Frame::Mapping {
+                    path,
+                    key: key @ None,
+                }) = self.stack.last_mut()
+                {
+                    self.positions.insert(join(path, &value), position(mark));
+                    *key = Some(value);
+                } else {
+                    self.value_path(mark);
+                    self.advance();
+                }
+            }
+            Event::Alias(_) => {
+                self.value_path(mark);
+                self.advance();
+            }
+            Event::MappingStart(..) => {
+                let path = self.value_path(mark);
+                self.stack.push(Frame::Mapping { path, key: None });
+            }
+            Event::SequenceStart(..) => {
+                let path = self.value_path(mark);
+                self.stack.push(Frame::Sequence { path, index: 0 });
+            }
+            Event::MappingEnd | Event::SequenceEnd => {
+                self.stack.pop();
+                self.advance();
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Converts a parser marker to a 1-based line and column
+fn position(mark: Marker) -> (usize, usize) {
+    (mark.line(), mark.col() + 1)
+}
+
+/// Appends a segment to a dotted key path
+fn join(parent: &str, segment: &str) -> String {
+    if parent.is_empty() {
+        segment.to_string()
+    } else {
+        format!("{}.{}", parent, segment)
+    }
+}
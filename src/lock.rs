@@ -0,0 +1,91 @@
+use std::fs::{self, OpenOptions};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+
+use crate::error::DeployError;
+
+/// Suffix of the lock file kept next to the locked directory
+const LOCK_SUFFIX: &str = ".ud-lock";
+
+/// Exclusive lock preventing concurrent deployments in one directory
+///
+/// The lock is a file next to the directory, `.<name>.ud-lock` in its
+/// parent, so a first non-clean deployment can still clone into the empty
+/// directory. It holds the owner's process id and is released when the
+/// value is dropped. A lock left behind by a process that no longer runs,
+/// e.g. one that was killed, is taken over.
+#[derive(Debug)]
+pub struct DeployLock {
+    path: PathBuf,
+}
+
+impl DeployLock {
+    /// Acquires the lock for a working directory
+    ///
+    /// # Returns
+    /// The held lock, or `DeployError::Locked` if another deployment holds it
+    pub fn acquire(dir: &Path) -> Result<Self, DeployError> {
+        let path = lock_path(dir);
+
+        match DeployLock::create(&path) {
+            Err(DeployError::Locked(_)) if is_stale(&path) => {
+                eprintln!("Warning: Removing stale lock {}", path.display());
+                fs::remove_file(&path)?;
+                DeployLock::create(&path)
+            }
+            result => result,
+        }
+    }
+
+    /// Creates the lock file, failing if it already exists
+    fn create(path: &Path) -> Result<Self, DeployError> {
+        match OpenOptions::new().write(true).create_new(true).open(path) {
+            Ok(mut file) => {
+                writeln!(file, "{}", std::process::id())?;
+                Ok(DeployLock {
+                    path: path.to_path_buf(),
+                })
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {
+                Err(DeployError::Locked(path.to_path_buf()))
+            }
+            Err(e) => Err(DeployError::Resource(format!(
+                "Could not create lock file {}: {}",
+                path.display(),
+                e
+            ))),
+        }
+    }
+}
+
+/// Returns the lock file of a directory, next to it in its parent
+fn lock_path(dir: &Path) -> PathBuf {
+    let name = dir
+        .file_name()
+        .map_or_else(|| "root".into(), |name| name.to_string_lossy());
+    dir.parent()
+        .unwrap_or(dir)
+        .join(format!(".{}{}", name, LOCK_SUFFIX))
+}
+
+/// Returns whether a lock file names a process that is no longer running
+///
+/// Only decided where `/proc` lists processes; elsewhere, and for lock
+/// files without a process id, the lock is assumed to be held.
+fn is_stale(path: &Path) -> bool {
+    let Some(pid) = fs::read_to_string(path)
+        .ok()
+        .and_then(|contents| contents.trim().parse::<u32>().ok())
+    else {
+        return false;
+    };
+
+    let proc = Path::new("/proc");
+    proc.join("self").exists() && !proc.join(pid.to_string()).exists()
+}
+
+impl Drop for DeployLock {
+    fn drop(&mut self) {
+        let _ = fs::remove_file(&self.path);
+    }
+}
@@ -1,119 +1,416 @@
 mod cli;
-mod config;
-mod git;
 
-use config::Config;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use universal_deploy::{
+    deployment,
+    format::{self, ConfigFormat},
+    lint,
+    overrides::{self, Override},
+    resolve, resources, secrets,
+    version::Bump,
+    Config, ConfigSource, DeployError, DeployLock, DeployOptions, Deployment, LoadOptions,
+    ReleaseStatus,
+};
 
 fn main() {
-    // Check if running the completion subcommand
-    let cmd = if cli::is_completion_command() {
-        cli::build_command_for_completion()
+    // Check if running a subcommand
+    let cmd = if cli::is_subcommand_invocation() {
+        cli::build_command_for_subcommand()
     } else {
         cli::build_command()
     };
 
     let matches = cmd.get_matches();
 
-    // Handle completion subcommand
-    if let Some(("completion", sub_matches)) = matches.subcommand() {
-        let shell: clap_complete::Shell = *sub_matches
-            .get_one("shell")
-            .expect("Shell is required");
+    match matches.subcommand() {
+        // Handle completion subcommand
+        Some(("completion", sub_matches)) => {
+            let shell: clap_complete::Shell = *sub_matches
+                .get_one("shell")
+                .expect("Shell is required");
 
-        cli::generate_completion(shell);
-        return;
+            cli::generate_completion(shell);
+            return;
+        }
+        // Handle config subcommand
+        Some(("config", sub_matches)) => {
+            if let Err(e) = run_config_command(sub_matches) {
+                exit_with_error(&e, false);
+            }
+            return;
+        }
+        // Handle resources subcommand
+        Some(("resources", sub_matches)) => {
+            if let Err(e) = run_resources_command(sub_matches) {
+                exit_with_error(&e, false);
+            }
+            return;
+        }
+        // Handle schema subcommand
+        Some(("schema", sub_matches)) => {
+            if let Err(e) = run_schema_command(sub_matches) {
+                exit_with_error(&e, false);
+            }
+            return;
+        }
+        // Handle secrets subcommand
+        Some(("secrets", sub_matches)) => {
+            if let Err(e) = run_secrets_command(sub_matches) {
+                exit_with_error(&e, false);
+            }
+            return;
+        }
+        // Handle validate subcommand
+        Some(("validate", sub_matches)) => {
+            if let Err(e) = run_validate_command(sub_matches) {
+                exit_with_error(&e, false);
+            }
+            return;
+        }
+        _ => {}
     }
 
     // Handle normal deployment operation
     match run_deployment(&matches) {
         Ok(_) => {}
-        Err(e) => {
-            eprintln!("Error: {}", e);
-            std::process::exit(1);
+        Err(e) => exit_with_error(&e, matches.get_flag("pass-exit-code")),
+    }
+}
+
+/// Prints an error and exits with the code of its failure class
+///
+/// With `pass_exit_code`, a failing tool's own exit code is used instead.
+fn exit_with_error(error: &DeployError, pass_exit_code: bool) -> ! {
+    eprintln!("Error: {}", error);
+
+    let code = match error {
+        DeployError::ToolFailed { code, .. } if pass_exit_code => *code,
+        _ => error.exit_code(),
+    };
+    std::process::exit(code);
+}
+
+/// Executes a `config` subcommand
+fn run_config_command(matches: &clap::ArgMatches) -> Result<(), DeployError> {
+    if let Some(("show", sub_matches)) = matches.subcommand() {
+        let config_path = sub_matches
+            .get_one::<String>("config")
+            .expect("Config file is required");
+        let source = ConfigSource::open(config_path, sub_matches.get_flag("verbose"))?;
+        let mut resolved = resolve::resolve(
+            source.path(),
+            sub_matches.get_one::<ConfigFormat>("format").copied(),
+        )?;
+        // Showing a configuration deploys nothing, so policies may be overridden
+        overrides::apply(&mut resolved, &set_values(sub_matches), true)?;
+
+        if sub_matches.get_flag("resolved") {
+            print!("{}", resolved.to_annotated_yaml());
+        } else {
+            let yaml = serde_yaml::to_string(&resolved.value).map_err(|e| {
+                DeployError::ValidationError(format!("Failed to format {}: {}", config_path, e))
+            })?;
+            print!("{}", yaml);
         }
     }
+
+    if let Some(("convert", sub_matches)) = matches.subcommand() {
+        let config_path = sub_matches
+            .get_one::<String>("config")
+            .expect("Config file is required");
+        let output = sub_matches.get_one::<String>("output");
+
+        if !Path::new(config_path).exists() {
+            return Err(DeployError::ConfigNotFound(config_path.to_string()));
+        }
+        let from = ConfigFormat::detect(
+            Path::new(config_path),
+            sub_matches.get_one::<ConfigFormat>("from").copied(),
+        );
+        let to = sub_matches
+            .get_one::<ConfigFormat>("to")
+            .copied()
+            .or_else(|| output.and_then(|output| ConfigFormat::from_path(Path::new(output))))
+            .ok_or_else(|| {
+                DeployError::ValidationError(
+                    "Specify --to or an --output path ending in .yml, .yaml, .toml or .json"
+                        .to_string(),
+                )
+            })?;
+
+        let contents =
+            std::fs::read_to_string(config_path).map_err(|e| DeployError::ConfigUnreadable {
+                path: config_path.to_string(),
+                message: e.to_string(),
+            })?;
+        let converted = format::convert(&contents, config_path, from, to)?;
+        match output {
+            Some(output) => {
+                std::fs::write(output, converted)?;
+                println!("Converted {} -> {}", config_path, output);
+            }
+            None => print!("{}", converted),
+        }
+    }
+
+    Ok(())
 }
 
-/// Executes the deployment workflow
-fn run_deployment(matches: &clap::ArgMatches) -> Result<(), Box<dyn std::error::Error>> {
+/// Executes a `resources` subcommand
+fn run_resources_command(matches: &clap::ArgMatches) -> Result<(), DeployError> {
+    if let Some(("lock", sub_matches)) = matches.subcommand() {
+        let config_path = sub_matches
+            .get_one::<String>("config")
+            .expect("Config file is required");
+        let verbose = sub_matches.get_flag("verbose");
+
+        let lockfile_path = resources::lock(config_path, verbose)?;
+        println!("Wrote lockfile: {}", lockfile_path.display());
+    }
+
+    Ok(())
+}
+
+/// Executes the `validate` subcommand
+fn run_validate_command(matches: &clap::ArgMatches) -> Result<(), DeployError> {
     let config_path = matches
         .get_one::<String>("config")
         .expect("Config file is required");
-    let verbose = matches.get_flag("verbose");
-    let keep_checkout = matches.get_flag("keep-checkout");
 
-    if verbose {
-        println!("Reading configuration from: {}", config_path);
+    let source = ConfigSource::open(config_path, matches.get_flag("verbose"))?;
+    let diagnostics = lint::lint(
+        source.path(),
+        &load_options(matches),
+        matches.get_flag("remote"),
+    )?;
+    for diagnostic in &diagnostics {
+        println!("{}", diagnostic);
     }
 
-    // Load and validate configuration
-    let config = Config::load(config_path)?;
-    config.validate()?;
+    match diagnostics.iter().filter(|d| !d.warning).count() {
+        0 => {
+            println!("{} is valid", config_path);
+            Ok(())
+        }
+        1 => Err(DeployError::ValidationError(format!(
+            "1 problem found in {}",
+            config_path
+        ))),
+        count => Err(DeployError::ValidationError(format!(
+            "{} problems found in {}",
+            count, config_path
+        ))),
+    }
+}
 
-    if verbose {
-        config.print_summary(true);
-    } else {
-        println!("Configuration loaded successfully from {}", config_path);
-        config.print_summary(false);
-    }
-
-    // Perform repository checkout
-    let repo_path = git::checkout_repository(
-        config_path,
-        &config.release.repository,
-        &config.release.branch,
-        config.release.clean,
-        config.release.merge,
-        verbose,
-        &config.release.resources,
-    )?;
+/// Executes the `schema` subcommand
+fn run_schema_command(matches: &clap::ArgMatches) -> Result<(), DeployError> {
+    let schema = serde_json::to_string_pretty(&Config::json_schema())
+        .expect("Schema is always serializable");
 
-    if verbose {
-        println!(
-            "Repository successfully checked out to: {}",
-            repo_path.display()
-        );
-    } else {
-        println!("Repository checked out successfully");
+    match matches.get_one::<String>("output") {
+        Some(output) => std::fs::write(output, schema + "\n")?,
+        None => println!("{}", schema),
     }
 
-    // Execute deployment tool if specified
-    let tool_result = if let Some(command) = config.release.tool.command() {
-        if verbose {
-            println!("Executing tool: '{}'", config.release.tool);
+    Ok(())
+}
+
+/// Executes a `secrets` subcommand
+fn run_secrets_command(matches: &clap::ArgMatches) -> Result<(), DeployError> {
+    let (name, sub_matches) = matches.subcommand().expect("Subcommand is required");
+    let file = Path::new(
+        sub_matches
+            .get_one::<String>("file")
+            .expect("File is required"),
+    );
+    let key_file = sub_matches.get_one::<String>("key-file").map(Path::new);
+
+    match name {
+        "encrypt" => {
+            let output = sub_matches
+                .get_one::<String>("output")
+                .map(PathBuf::from)
+                .unwrap_or_else(|| secrets::encrypted_path(file));
+            secrets::encrypt_file(file, &output, key_file)?;
+            println!("Encrypted {} -> {}", file.display(), output.display());
         }
-        let exit_code = git::execute_tool(
-            command,
-            config.release.tool.arguments(),
-            &repo_path,
-            verbose,
-        )?;
-        if exit_code != 0 {
-            Err(format!(
-                "Tool '{}' failed with exit code {}",
-                config.release.tool, exit_code
-            )
-            .into())
-        } else {
-            if verbose {
-                println!("Tool execution completed successfully");
+        "decrypt" => {
+            let plaintext = secrets::decrypt_file(file, key_file)?;
+            match sub_matches.get_one::<String>("output") {
+                Some(output) => secrets::write_private(Path::new(output), &plaintext)?,
+                None => std::io::stdout().write_all(&plaintext)?,
             }
-            Ok(())
         }
+        "edit" => {
+            if secrets::edit_file(file, key_file)? {
+                println!("Updated {}", file.display());
+            } else {
+                println!("No changes to {}", file.display());
+            }
+        }
+        _ => unreachable!("Unknown secrets subcommand"),
+    }
+
+    Ok(())
+}
+
+/// Builds the options for loading a configuration from `--format`, `--lenient` and `--set`
+fn load_options(matches: &clap::ArgMatches) -> LoadOptions {
+    LoadOptions {
+        format: matches.get_one::<ConfigFormat>("format").copied(),
+        lenient: matches.get_flag("lenient"),
+        overrides: set_values(matches),
+        override_policy: matches.get_flag("override-policy"),
+    }
+}
+
+/// Returns the values of the repeatable `--set` argument
+fn set_values(matches: &clap::ArgMatches) -> Vec<Override> {
+    matches
+        .get_many::<Override>("set")
+        .map(|values| values.cloned().collect())
+        .unwrap_or_default()
+}
+
+/// Executes the deployment workflow
+fn run_deployment(matches: &clap::ArgMatches) -> Result<(), DeployError> {
+    let config_path = matches
+        .get_one::<String>("config")
+        .expect("Config file is required");
+    let options = DeployOptions {
+        verbose: matches.get_flag("verbose"),
+        keep_checkout: matches.get_flag("keep-checkout"),
+        dry_run: matches.get_flag("dry-run"),
+        env: matches.get_one::<String>("env").cloned(),
+        assume_yes: matches.get_flag("yes"),
+        show_diff_stat: matches.get_flag("show-diff-stat"),
+        bump: matches.get_one::<Bump>("bump").copied(),
+        override_policy: matches.get_flag("override-policy"),
+    };
+
+    if options.verbose {
+        println!("Reading configuration from: {}", config_path);
+    }
+
+    // Held until the deployment returns, as resources are read from next to the file
+    let source = ConfigSource::open(config_path, options.verbose)?;
+
+    // Load and validate configuration
+    let config = Config::load_with(source.path(), &load_options(matches))?;
+    config.validate_with(options.override_policy)?;
+
+    let names: Vec<String> = if matches.get_flag("all") {
+        config
+            .release_names()
+            .into_iter()
+            .map(String::from)
+            .collect()
     } else {
-        Ok(())
+        matches
+            .get_many::<String>("releases")
+            .map(|names| names.cloned().collect())
+            .unwrap_or_default()
     };
 
-    // Cleanup checkout directory if in clean mode and not keeping it
-    if config.release.clean && !keep_checkout {
-        if let Err(e) = std::fs::remove_dir_all(&repo_path) {
-            eprintln!("Warning: Failed to remove checkout directory: {}", e);
-        } else if verbose {
-            println!("Removed checkout directory: {}", repo_path.display());
+    if config.releases.is_empty() {
+        if !names.is_empty() {
+            return Err(DeployError::ValidationError(format!(
+                "{} defines a single release; release names can only select entries of `releases`",
+                config_path
+            )));
+        }
+
+        if options.verbose {
+            config.print_summary(true);
+        } else {
+            println!("Configuration loaded successfully from {}", config_path);
+            config.print_summary(false);
         }
-    } else if config.release.clean && verbose {
-        println!("Keeping checkout directory: {}", repo_path.display());
+
+        check_bump(&options, &[&config])?;
+
+        // Held until the deployment returns, so it is released before exiting
+        let _lock = acquire_lock(&options)?;
+
+        return Deployment::new(source.path(), config, options).run();
+    }
+
+    if names.is_empty() {
+        return Err(DeployError::ValidationError(format!(
+            "{} defines several releases; name the ones to run or pass --all (available: {})",
+            config_path,
+            config.release_names().join(", ")
+        )));
+    }
+
+    if !options.verbose {
+        println!("Configuration loaded successfully from {}", config_path);
+    }
+    let mut releases = Vec::new();
+    for name in &names {
+        releases.push((name, config.release_named(name)?));
     }
+    let selected: Vec<&Config> = releases.iter().map(|(_, release)| release).collect();
+    check_bump(&options, &selected)?;
 
-    tool_result
+    let mut deployments = Vec::new();
+    for name in config.release_order(&names) {
+        let (_, release) = releases
+            .iter()
+            .find(|(n, _)| **n == name)
+            .expect("Ordered releases are selected");
+        println!("Release '{}':", name);
+        release.print_summary(options.verbose);
+        let deployment =
+            Deployment::new(source.path(), release.clone(), options.clone()).with_name(&name);
+        deployments.push((name, deployment));
+    }
+
+    let _lock = acquire_lock(&options)?;
+
+    let jobs = match matches.get_one::<u16>("jobs") {
+        Some(&jobs) => usize::from(jobs),
+        None if matches.get_flag("parallel") => deployments.len(),
+        None => 1,
+    };
+    let outcomes = deployment::run_releases(deployments, jobs)?;
+    deployment::print_outcomes(&outcomes);
+
+    // Exit with the first failure, so its class decides the exit code
+    let failure = outcomes
+        .into_iter()
+        .find_map(|outcome| match outcome.status {
+            ReleaseStatus::Failed(e) => Some(e),
+            _ => None,
+        });
+    match failure {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+/// Refuses `--bump` unless at least one selected release has a `version`
+///
+/// Releases without `version` ignore the flag.
+fn check_bump(options: &DeployOptions, releases: &[&Config]) -> Result<(), DeployError> {
+    if options.bump.is_some()
+        && releases
+            .iter()
+            .all(|release| release.release.version.is_none())
+    {
+        return Err(DeployError::ValidationError(
+            "--bump needs `version` in at least one selected release".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+/// Takes the deployment lock for the current directory, unless this is a dry run
+fn acquire_lock(options: &DeployOptions) -> Result<Option<DeployLock>, DeployError> {
+    if options.dry_run {
+        return Ok(None);
+    }
+    Ok(Some(DeployLock::acquire(&std::env::current_dir()?)?))
 }
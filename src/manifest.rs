@@ -0,0 +1,228 @@
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use crate::config::ResourceCleanup;
+use crate::error::DeployError;
+use crate::secrets;
+
+/// Directory inside the repository where `ud` keeps its own state
+pub const STATE_DIR: &str = ".ud";
+
+/// File name of the manifest inside the state directory
+const MANIFEST_FILE: &str = "manifest.yml";
+
+/// Directory inside the state directory holding replaced originals
+const BACKUP_DIR: &str = "backup";
+
+/// Files written into a repository by `ud`
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Manifest {
+    /// Written files, relative to the repository root
+    #[serde(default)]
+    pub files: Vec<ManifestEntry>,
+}
+
+/// A file written by `ud`
+#[derive(Debug, Deserialize, Serialize)]
+pub struct ManifestEntry {
+    /// Path relative to the repository root
+    pub path: String,
+
+    /// Backup of the content that existed before, relative to the state directory
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub backup: Option<String>,
+}
+
+impl Manifest {
+    /// Loads the manifest of a repository, or an empty one if none exists
+    pub fn load(repo_path: &Path) -> Result<Self, DeployError> {
+        let path = manifest_path(repo_path);
+        if !path.exists() {
+            return Ok(Manifest::default());
+        }
+
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            DeployError::Resource(format!("Could not read manifest {}: {}", path.display(), e))
+        })?;
+
+        let manifest: Manifest = serde_yaml::from_str(&contents).map_err(|e| {
+            DeployError::Resource(format!(
+                "Failed to parse manifest {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+
+        Ok(manifest)
+    }
+
+    /// Writes the manifest, removing it once no files are tracked
+    pub fn save(&self, repo_path: &Path) -> Result<(), DeployError> {
+        let path = manifest_path(repo_path);
+        if self.files.is_empty() {
+            if path.exists() {
+                fs::remove_file(&path)?;
+            }
+            return Ok(());
+        }
+
+        ensure_state_dir(repo_path)?;
+        let yaml = serde_yaml::to_string(self)
+            .map_err(|e| DeployError::Resource(format!("Failed to serialize manifest: {}", e)))?;
+        fs::write(&path, yaml).map_err(|e| {
+            DeployError::Resource(format!(
+                "Could not write manifest {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(())
+    }
+
+    /// Returns the set of paths written by `ud`
+    pub fn paths(&self) -> BTreeSet<&str> {
+        self.files.iter().map(|entry| entry.path.as_str()).collect()
+    }
+
+    /// Records a written file, backing up the content it replaced
+    ///
+    /// Files already in the manifest keep their original backup, so the
+    /// content from before the first deployment is what gets restored.
+    ///
+    /// # Arguments
+    /// * `repo_path` - Repository root
+    /// * `path` - Written path, relative to the repository root
+    /// * `previous` - Content of the file before it was written, if it existed
+    pub fn record(
+        &mut self,
+        repo_path: &Path,
+        path: &str,
+        previous: Option<&[u8]>,
+    ) -> Result<(), DeployError> {
+        if self.files.iter().any(|entry| entry.path == path) {
+            return Ok(());
+        }
+
+        let backup = match previous {
+            Some(contents) => {
+                let backup = Path::new(BACKUP_DIR).join(path);
+                let backup_path = repo_path.join(STATE_DIR).join(&backup);
+                if let Some(parent) = backup_path.parent() {
+                    fs::create_dir_all(parent)?;
+                }
+                // Originals may be as sensitive as the resources replacing them
+                secrets::write_private(&backup_path, contents)?;
+                Some(backup.to_string_lossy().into_owned())
+            }
+            None => None,
+        };
+
+        self.files.push(ManifestEntry {
+            path: path.to_string(),
+            backup,
+        });
+        self.save(repo_path)
+    }
+
+    /// Removes or restores the files written by `ud`
+    ///
+    /// Files that replaced existing content are always restored from their
+    /// backup, so the original is never lost; only files `ud` created are
+    /// removed. Entries that fail stay in the manifest, and the backups are
+    /// kept until every file has been restored.
+    ///
+    /// # Arguments
+    /// * `repo_path` - Repository root
+    /// * `policy` - What to do with the written files
+    /// * `verbose` - Enable verbose logging
+    pub fn cleanup(
+        &mut self,
+        repo_path: &Path,
+        policy: ResourceCleanup,
+        verbose: bool,
+    ) -> Result<(), DeployError> {
+        if policy == ResourceCleanup::Keep || self.files.is_empty() {
+            return Ok(());
+        }
+
+        let state_dir = repo_path.join(STATE_DIR);
+        let mut failure = None;
+        let mut remaining = Vec::new();
+        for entry in self.files.drain(..) {
+            match cleanup_entry(repo_path, &state_dir, &entry, verbose) {
+                Ok(()) => {}
+                Err(e) => {
+                    failure.get_or_insert(e);
+                    remaining.push(entry);
+                }
+            }
+        }
+        self.files = remaining;
+
+        // Backups are only dropped once nothing needs them any more
+        let backup_dir = state_dir.join(BACKUP_DIR);
+        if self.files.is_empty() && backup_dir.exists() {
+            fs::remove_dir_all(&backup_dir).map_err(|e| {
+                DeployError::Resource(format!(
+                    "Could not remove backups in {}: {}",
+                    backup_dir.display(),
+                    e
+                ))
+            })?;
+        }
+
+        self.save(repo_path)?;
+        failure.map_or(Ok(()), Err)
+    }
+}
+
+/// Restores a replaced file from its backup, or removes a file `ud` created
+fn cleanup_entry(
+    repo_path: &Path,
+    state_dir: &Path,
+    entry: &ManifestEntry,
+    verbose: bool,
+) -> Result<(), DeployError> {
+    let path = repo_path.join(&entry.path);
+    match &entry.backup {
+        Some(backup) => {
+            fs::copy(state_dir.join(backup), &path).map_err(|e| {
+                DeployError::Resource(format!("Could not restore {}: {}", entry.path, e))
+            })?;
+            if verbose {
+                println!("Restored {}", entry.path);
+            }
+        }
+        None => {
+            if path.exists() {
+                fs::remove_file(&path).map_err(|e| {
+                    DeployError::Resource(format!("Could not remove {}: {}", entry.path, e))
+                })?;
+            }
+            if verbose {
+                println!("Removed {}", entry.path);
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Returns the manifest path of a repository
+fn manifest_path(repo_path: &Path) -> PathBuf {
+    repo_path.join(STATE_DIR).join(MANIFEST_FILE)
+}
+
+/// Creates the state directory, ignored by git so it never dirties the worktree
+pub fn ensure_state_dir(repo_path: &Path) -> Result<PathBuf, DeployError> {
+    let state_dir = repo_path.join(STATE_DIR);
+    fs::create_dir_all(&state_dir)?;
+
+    let gitignore = state_dir.join(".gitignore");
+    if !gitignore.exists() {
+        fs::write(gitignore, "*\n")?;
+    }
+
+    Ok(state_dir)
+}
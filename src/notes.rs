@@ -0,0 +1,333 @@
+use std::fs;
+use std::path::Path;
+
+use crate::error::DeployError;
+use crate::git::{self, CommitInfo};
+
+/// Sections of the release notes, in order, with the commit types they collect
+const SECTIONS: &[(&str, &[&str])] = &[
+    ("Features", &["feat"]),
+    ("Bug Fixes", &["fix"]),
+    ("Performance", &["perf"]),
+    ("Refactoring", &["refactor"]),
+    ("Documentation", &["docs"]),
+    ("Chores", &["chore", "build", "ci", "style", "test"]),
+];
+
+/// Heading of the section listing breaking changes
+const BREAKING_SECTION: &str = "Breaking Changes";
+
+/// Heading of the section listing commits without a known type
+const OTHER_SECTION: &str = "Other Changes";
+
+/// A commit subject split into its conventional-commit parts
+///
+/// `feat(api)!: add paging` has the type `feat`, the scope `api`, is
+/// breaking and has the description `add paging`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ConventionalCommit<'a> {
+    /// Type, e.g. `feat` or `fix`, lowercased
+    pub kind: String,
+    /// Scope in parentheses, if any
+    pub scope: Option<&'a str>,
+    /// Whether the type is followed by `!` or the subject starts with `BREAKING CHANGE`
+    pub breaking: bool,
+    /// Text after the colon
+    pub description: &'a str,
+}
+
+impl<'a> ConventionalCommit<'a> {
+    /// Parses a commit subject, returning `None` if it doesn't follow the convention
+    pub fn parse(subject: &'a str) -> Option<Self> {
+        let (head, description) = subject.split_once(':')?;
+        let description = description.trim();
+        if description.is_empty() {
+            return None;
+        }
+
+        if head == "BREAKING CHANGE" || head == "BREAKING-CHANGE" {
+            return Some(ConventionalCommit {
+                kind: String::new(),
+                scope: None,
+                breaking: true,
+                description,
+            });
+        }
+
+        let (head, breaking) = match head.strip_suffix('!') {
+            Some(head) => (head, true),
+            None => (head, false),
+        };
+        let (kind, scope) = match head.split_once('(') {
+            Some((kind, scope)) => (kind, Some(scope.strip_suffix(')')?)),
+            None => (head, None),
+        };
+        if kind.is_empty() || !kind.chars().all(|c| c.is_ascii_alphabetic()) {
+            return None;
+        }
+
+        Some(ConventionalCommit {
+            kind: kind.to_ascii_lowercase(),
+            scope,
+            breaking,
+            description,
+        })
+    }
+}
+
+/// Renders release notes in Markdown, grouping commits by their conventional-commit type
+///
+/// # Arguments
+/// * `title` - Heading of the notes, e.g. the tag name
+/// * `date` - Date shown next to the title, as `YYYY-MM-DD`
+/// * `commits` - Commits in the release, newest first
+pub fn render(title: &str, date: &str, commits: &[CommitInfo]) -> String {
+    let mut sections: Vec<(&str, Vec<String>)> = Vec::new();
+    let mut add = |section: &'static str, line: String| match sections
+        .iter_mut()
+        .find(|(name, _)| *name == section)
+    {
+        Some((_, lines)) => lines.push(line),
+        None => sections.push((section, vec![line])),
+    };
+
+    for commit in commits {
+        let short = git::short_sha(&commit.sha);
+        let Some(parsed) = ConventionalCommit::parse(&commit.subject) else {
+            add(OTHER_SECTION, format!("- {} ({})", commit.subject, short));
+            continue;
+        };
+
+        let line = match parsed.scope {
+            Some(scope) => format!("- **{}:** {} ({})", scope, parsed.description, short),
+            None => format!("- {} ({})", parsed.description, short),
+        };
+        if parsed.breaking {
+            add(BREAKING_SECTION, line.clone());
+        }
+        let section = SECTIONS
+            .iter()
+            .find(|(_, kinds)| kinds.contains(&parsed.kind.as_str()))
+            .map_or(OTHER_SECTION, |(name, _)| name);
+        // Breaking changes without a type only appear under their own heading
+        if !(parsed.breaking && parsed.kind.is_empty()) {
+            add(section, line);
+        }
+    }
+
+    let order: Vec<&str> = std::iter::once(BREAKING_SECTION)
+        .chain(SECTIONS.iter().map(|(name, _)| *name))
+        .chain(std::iter::once(OTHER_SECTION))
+        .collect();
+    sections.sort_by_key(|(name, _)| order.iter().position(|o| o == name));
+
+    let mut notes = format!("## {} ({})\n", title, date);
+    if sections.is_empty() {
+        notes.push_str("\nNo changes.\n");
+    }
+    for (name, lines) in sections {
+        notes.push_str(&format!("\n### {}\n\n", name));
+        for line in lines {
+            notes.push_str(&line);
+            notes.push('\n');
+        }
+    }
+    notes
+}
+
+/// Adds release notes to the top of a changelog, below its main heading if it has one
+///
+/// # Arguments
+/// * `path` - Changelog file, created if missing
+/// * `notes` - Notes as returned by [`render`]
+pub fn prepend_to_changelog(path: &Path, notes: &str) -> Result<(), DeployError> {
+    let existing = if path.exists() {
+        fs::read_to_string(path)?
+    } else {
+        String::new()
+    };
+
+    let (heading, rest) = match existing.strip_prefix("# ") {
+        Some(_) => match existing.split_once('\n') {
+            Some((heading, rest)) => (format!("{}\n\n", heading), rest.trim_start()),
+            None => (format!("{}\n\n", existing), ""),
+        },
+        None if existing.is_empty() => ("# Changelog\n\n".to_string(), ""),
+        None => (String::new(), existing.as_str()),
+    };
+
+    let mut contents = heading;
+    contents.push_str(notes);
+    if !rest.is_empty() {
+        contents.push('\n');
+        contents.push_str(rest);
+    }
+    fs::write(path, contents)?;
+    Ok(())
+}
+
+/// Returns the UTC date and time of a Unix timestamp as `(year, month, day, hour, minute, second)`
+pub fn utc_datetime(secs: u64) -> (i64, u32, u32, u32, u32, u32) {
+    let days = (secs / 86_400) as i64;
+    let seconds = (secs % 86_400) as u32;
+
+    // Days since 1970-01-01 to a civil date, after Howard Hinnant's algorithm
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z.rem_euclid(146_097);
+    let yoe = (doe - doe / 1_460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+
+    (
+        year,
+        month,
+        day,
+        seconds / 3_600,
+        seconds % 3_600 / 60,
+        seconds % 60,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn commit(sha: &str, subject: &str) -> CommitInfo {
+        CommitInfo {
+            sha: sha.to_string(),
+            author: "Test".to_string(),
+            subject: subject.to_string(),
+        }
+    }
+
+    /// Returns a changelog path in a fresh temporary directory
+    fn changelog_path() -> std::path::PathBuf {
+        let dir = std::env::temp_dir().join(format!("ud-notes-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&dir).unwrap();
+        dir.join("CHANGELOG.md")
+    }
+
+    #[test]
+    fn parses_type_and_scope() {
+        let parsed = ConventionalCommit::parse("Feat(api): add paging").unwrap();
+        assert_eq!(parsed.kind, "feat");
+        assert_eq!(parsed.scope, Some("api"));
+        assert!(!parsed.breaking);
+        assert_eq!(parsed.description, "add paging");
+    }
+
+    #[test]
+    fn parses_breaking_markers() {
+        let bang = ConventionalCommit::parse("fix(db)!: drop old column").unwrap();
+        assert_eq!(bang.kind, "fix");
+        assert_eq!(bang.scope, Some("db"));
+        assert!(bang.breaking);
+
+        let footer = ConventionalCommit::parse("BREAKING CHANGE: config keys renamed").unwrap();
+        assert_eq!(footer.kind, "");
+        assert!(footer.breaking);
+        assert_eq!(footer.description, "config keys renamed");
+    }
+
+    #[test]
+    fn rejects_non_conventional_subjects() {
+        for subject in [
+            "Update README",
+            "Merge branch 'main': sync",
+            "feat(api: unclosed scope",
+            "feat:",
+            ": no type",
+        ] {
+            assert_eq!(ConventionalCommit::parse(subject), None, "{}", subject);
+        }
+    }
+
+    #[test]
+    fn renders_sections_in_order() {
+        let commits = [
+            commit("1111111aaaa", "Update README"),
+            commit("2222222bbbb", "fix(db)!: drop old column"),
+            commit("3333333cccc", "BREAKING CHANGE: config keys renamed"),
+            commit("4444444dddd", "feat: add paging"),
+        ];
+
+        let notes = render("v1.0.0", "2024-02-29", &commits);
+
+        assert_eq!(
+            notes,
+            "## v1.0.0 (2024-02-29)\n\
+             \n### Breaking Changes\n\n\
+             - **db:** drop old column (2222222)\n\
+             - config keys renamed (3333333)\n\
+             \n### Features\n\n\
+             - add paging (4444444)\n\
+             \n### Bug Fixes\n\n\
+             - **db:** drop old column (2222222)\n\
+             \n### Other Changes\n\n\
+             - Update README (1111111)\n"
+        );
+    }
+
+    #[test]
+    fn renders_no_changes() {
+        assert_eq!(
+            render("v1.0.0", "2024-02-29", &[]),
+            "## v1.0.0 (2024-02-29)\n\nNo changes.\n"
+        );
+    }
+
+    #[test]
+    fn prepends_below_changelog_heading() {
+        let path = changelog_path();
+        fs::write(&path, "# Changelog\n\n## v1.0.0 (2024-01-01)\n").unwrap();
+
+        prepend_to_changelog(&path, "## v1.1.0 (2024-02-29)\n").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Changelog\n\n## v1.1.0 (2024-02-29)\n\n## v1.0.0 (2024-01-01)\n"
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn prepends_to_changelog_without_heading() {
+        let path = changelog_path();
+        fs::write(&path, "## v1.0.0 (2024-01-01)\n").unwrap();
+
+        prepend_to_changelog(&path, "## v1.1.0 (2024-02-29)\n").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "## v1.1.0 (2024-02-29)\n\n## v1.0.0 (2024-01-01)\n"
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn creates_missing_changelog_with_heading() {
+        let path = changelog_path();
+
+        prepend_to_changelog(&path, "## v1.0.0 (2024-02-29)\n").unwrap();
+
+        assert_eq!(
+            fs::read_to_string(&path).unwrap(),
+            "# Changelog\n\n## v1.0.0 (2024-02-29)\n"
+        );
+        fs::remove_dir_all(path.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn converts_timestamps_around_leap_days() {
+        assert_eq!(utc_datetime(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(utc_datetime(1_709_209_805), (2024, 2, 29, 12, 30, 5));
+        assert_eq!(utc_datetime(1_704_067_199), (2023, 12, 31, 23, 59, 59));
+        // 2000 is a leap year, 2100 isn't
+        assert_eq!(utc_datetime(951_782_400), (2000, 2, 29, 0, 0, 0));
+        assert_eq!(utc_datetime(4_107_542_400), (2100, 3, 1, 0, 0, 0));
+    }
+}
@@ -0,0 +1,305 @@
+use serde_json::Value as Schema;
+use serde_yaml::Value;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::config::Config;
+use crate::error::DeployError;
+use crate::resolve::{Origin, Resolved};
+
+/// Name recorded as the origin of overridden values
+const OVERRIDE_ORIGIN: &str = "--set";
+
+/// Section that paths without a known top-level key are relative to
+const DEFAULT_SECTION: &str = "release";
+
+/// Release keys that guard what may be deployed, only overridden with `--override-policy`
+const POLICY_KEYS: &[&str] = &[
+    "allowed_branches",
+    "require_tag_pattern",
+    "verify_signature",
+    "allowed_signers",
+    "protected_environments",
+    "confirm",
+];
+
+/// A configuration value set on the command line, e.g. `release.branch=hotfix`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Override {
+    /// Dotted key path, always starting at the top level
+    pub path: String,
+    /// Value as written on the command line
+    pub raw: String,
+}
+
+impl FromStr for Override {
+    type Err = String;
+
+    /// Parses `path=value`, where `path` may use `name[index]` for list items
+    /// and may leave out the leading `release.`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (path, raw) = s
+            .split_once('=')
+            .ok_or_else(|| format!("Expected PATH=VALUE, got '{}'", s))?;
+
+        let mut segments = Vec::new();
+        for part in path.split('.') {
+            let (name, indexes) = match part.find('[') {
+                Some(bracket) => part.split_at(bracket),
+                None => (part, ""),
+            };
+            if name.is_empty() {
+                return Err(format!("Invalid path '{}'", path));
+            }
+            segments.push(name.to_string());
+
+            let mut indexes = indexes;
+            while let Some(rest) = indexes.strip_prefix('[') {
+                let (index, rest) = rest
+                    .split_once(']')
+                    .filter(|(index, _)| index.parse::<usize>().is_ok())
+                    .ok_or_else(|| format!("Invalid list index in '{}'", path))?;
+                segments.push(index.to_string());
+                indexes = rest;
+            }
+            if !indexes.is_empty() {
+                return Err(format!("Invalid path '{}'", path));
+            }
+        }
+
+        let schema = schema();
+        if property(&schema, &schema, &segments[0]).is_none() {
+            segments.insert(0, DEFAULT_SECTION.to_string());
+        }
+
+        Ok(Override {
+            path: segments.join("."),
+            raw: raw.to_string(),
+        })
+    }
+}
+
+impl fmt::Display for Override {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}={}", self.path, self.raw)
+    }
+}
+
+impl Override {
+    /// Returns whether the override changes a policy key, or a section holding one
+    ///
+    /// # Arguments
+    /// * `release` - Only consider overrides of this entry under `releases`; any entry if `None`
+    pub fn changes_policy(&self, release: Option<&str>) -> bool {
+        let segments: Vec<&str> = self.path.split('.').collect();
+        let key = match segments.as_slice() {
+            ["release", rest @ ..] => rest.first(),
+            ["releases"] => None,
+            ["releases", name, rest @ ..] => {
+                if release.is_some_and(|release| release != *name) {
+                    return false;
+                }
+                rest.first()
+            }
+            _ => return false,
+        };
+        key.is_none_or(|key| POLICY_KEYS.contains(key))
+    }
+
+    /// Returns the value to set, typed according to what the configuration
+    /// accepts at the override's path
+    ///
+    /// The raw text is read as YAML, so `true` or `3` become a boolean or a
+    /// number. Where that type isn't accepted but a string is, the raw text
+    /// is used as a string, so `--set release.branch=123` works as expected.
+    pub fn value(&self) -> Result<Value, DeployError> {
+        let parsed = serde_yaml::from_str::<Value>(&self.raw).unwrap_or(Value::Null);
+        let types = accepted_types(&self.path);
+
+        // Paths the configuration doesn't know are reported as unknown keys later
+        if types.is_empty() || types.iter().any(|t| type_matches(t, &parsed)) {
+            return Ok(parsed);
+        }
+        if types.iter().any(|t| t == "string") {
+            return Ok(Value::String(self.raw.clone()));
+        }
+
+        Err(DeployError::ValidationError(format!(
+            "Invalid value for {}: expected {}, got '{}'",
+            self.path,
+            types.join(" or "),
+            self.raw
+        )))
+    }
+}
+
+/// Applies overrides to a resolved configuration, in order
+///
+/// # Arguments
+/// * `resolved` - Configuration to change
+/// * `overrides` - Values set on the command line
+/// * `override_policy` - Whether policy keys may be overridden; they are refused otherwise
+pub fn apply(
+    resolved: &mut Resolved,
+    overrides: &[Override],
+    override_policy: bool,
+) -> Result<(), DeployError> {
+    for item in overrides {
+        // Policies would check nothing if `--set` could loosen them
+        if item.changes_policy(None) {
+            if !override_policy {
+                return Err(DeployError::ValidationError(format!(
+                    "Can't set {}: it changes a branch or deployment policy (pass --override-policy to set it anyway)",
+                    item.path
+                )));
+            }
+            eprintln!("Warning: Overriding policy: --set {}", item);
+        }
+
+        // `release.` would add a section next to `releases` instead of changing one
+        if let Some(rest) = item.path.strip_prefix("release.") {
+            if resolved.value.get("releases").is_some() {
+                return Err(DeployError::ValidationError(format!(
+                    "Can't set {}: the configuration defines `releases`, set releases.<name>.{} instead",
+                    item.path, rest
+                )));
+            }
+        }
+
+        let origin = Origin {
+            file: OVERRIDE_ORIGIN.to_string(),
+            position: None,
+        };
+        resolved
+            .set(&item.path, item.value()?, origin)
+            .map_err(|message| {
+                DeployError::ValidationError(format!("Can't set {}: {}", item.path, message))
+            })?;
+    }
+    Ok(())
+}
+
+/// Returns the JSON Schema of the configuration as JSON
+fn schema() -> Schema {
+    serde_json::to_value(Config::json_schema()).expect("Schema is always serializable")
+}
+
+/// Returns the JSON types accepted at a dotted key path, empty if the path is unknown
+fn accepted_types(path: &str) -> Vec<String> {
+    let schema = schema();
+    let mut nodes = vec![&schema];
+
+    for segment in path.split('.') {
+        nodes = nodes
+            .into_iter()
+            .flat_map(|node| alternatives(&schema, node))
+            .filter_map(|node| match segment.parse::<usize>() {
+                Ok(_) if node.get("items").is_some() => node.get("items"),
+                _ => property(&schema, node, segment),
+            })
+            .collect();
+    }
+
+    let mut types: Vec<String> = Vec::new();
+    for node in nodes
+        .into_iter()
+        .flat_map(|node| alternatives(&schema, node))
+    {
+        let names = match node.get("type") {
+            Some(Schema::String(name)) => vec![name.as_str()],
+            Some(Schema::Array(names)) => names.iter().filter_map(Schema::as_str).collect(),
+            // Enums are lists of string constants
+            _ if node.get("const").is_some_and(Schema::is_string) => vec!["string"],
+            _ => Vec::new(),
+        };
+        for name in names {
+            if !types.iter().any(|t| t == name) {
+                types.push(name.to_string());
+            }
+        }
+    }
+    types
+}
+
+/// Returns the schema of a map entry, following references and alternatives
+fn property<'a>(root: &'a Schema, node: &'a Schema, key: &str) -> Option<&'a Schema> {
+    alternatives(root, node).into_iter().find_map(|node| {
+        node.get("properties")
+            .and_then(|properties| properties.get(key))
+            .or_else(|| node.get("additionalProperties").filter(|s| s.is_object()))
+    })
+}
+
+/// Expands a schema into the schemas it may match, following `$ref`, `anyOf` and `oneOf`
+fn alternatives<'a>(root: &'a Schema, node: &'a Schema) -> Vec<&'a Schema> {
+    if let Some(reference) = node.get("$ref").and_then(Schema::as_str) {
+        let name = reference.trim_start_matches("#/$defs/");
+        return match root.get("$defs").and_then(|defs| defs.get(name)) {
+            Some(target) => alternatives(root, target),
+            None => Vec::new(),
+        };
+    }
+
+    match node.get("anyOf").or_else(|| node.get("oneOf")) {
+        Some(Schema::Array(options)) => options
+            .iter()
+            .flat_map(|option| alternatives(root, option))
+            .collect(),
+        _ => vec![node],
+    }
+}
+
+/// Returns whether a YAML value has the given JSON type
+fn type_matches(name: &str, value: &Value) -> bool {
+    match (name, value) {
+        ("string", Value::String(_)) | ("boolean", Value::Bool(_)) => true,
+        ("integer", Value::Number(n)) => n.is_i64() || n.is_u64(),
+        ("number", Value::Number(_)) => true,
+        ("array", Value::Sequence(_)) | ("object", Value::Mapping(_)) => true,
+        ("null", Value::Null) => true,
+        _ => false,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(s: &str) -> Override {
+        s.parse().unwrap()
+    }
+
+    #[test]
+    fn prefixes_release_section() {
+        assert_eq!(parse("branch=main").path, "release.branch");
+        assert_eq!(
+            parse("tool.arguments[1]=x").path,
+            "release.tool.arguments.1"
+        );
+        assert_eq!(parse("releases.api.branch=x").path, "releases.api.branch");
+    }
+
+    #[test]
+    fn detects_policy_keys() {
+        assert!(parse("allowed_branches=[]").changes_policy(None));
+        assert!(parse("release.confirm=false").changes_policy(None));
+        assert!(parse("releases.api.verify_signature=commit").changes_policy(None));
+        assert!(!parse("branch=main").changes_policy(None));
+        assert!(!parse("tool.arguments[0]=x").changes_policy(None));
+    }
+
+    #[test]
+    fn detects_sections_holding_policy_keys() {
+        assert!(parse("release={}").changes_policy(None));
+        assert!(parse("releases={}").changes_policy(None));
+        assert!(parse("releases.api={}").changes_policy(None));
+    }
+
+    #[test]
+    fn scopes_policy_overrides_to_their_release() {
+        let item = parse("releases.api.confirm=false");
+        assert!(item.changes_policy(Some("api")));
+        assert!(!item.changes_policy(Some("web")));
+        assert!(parse("release.confirm=false").changes_policy(Some("web")));
+    }
+}
@@ -0,0 +1,541 @@
+use indexmap::IndexMap;
+use serde_yaml::Value;
+use std::collections::BTreeMap;
+use std::fmt;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+
+use crate::error::DeployError;
+use crate::format::ConfigFormat;
+use crate::lint::KeyLocator;
+use crate::source;
+
+/// Name used for a configuration read from standard input in messages
+const STDIN_NAME: &str = "<stdin>";
+
+/// Key naming the configuration file a file is based on
+const EXTENDS_KEY: &str = "extends";
+
+/// Key listing configuration files merged into a file
+const INCLUDE_KEY: &str = "include";
+
+/// Suffix marking a list that is appended to the inherited one instead of replacing it
+const APPEND_SUFFIX: char = '+';
+
+/// Where a configuration value was defined
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Origin {
+    /// File the value was read from
+    pub file: String,
+    /// 1-based line and column of its key, if known
+    pub position: Option<(usize, usize)>,
+}
+
+impl fmt::Display for Origin {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.position {
+            Some((line, _)) => write!(f, "{}:{}", self.file, line),
+            None => write!(f, "{}", self.file),
+        }
+    }
+}
+
+/// A configuration with its `extends` and `include` files merged in
+#[derive(Debug)]
+pub struct Resolved {
+    /// Path of the configuration file that was loaded
+    pub path: String,
+    /// Merged configuration
+    pub value: Value,
+    /// Dotted key path of every value mapped to where it was defined
+    pub origins: BTreeMap<String, Origin>,
+}
+
+impl Resolved {
+    /// Returns where `path` was defined, falling back to its nearest known parent
+    pub fn origin(&self, path: &str) -> Option<&Origin> {
+        let mut path = path;
+        loop {
+            if let Some(origin) = self.origins.get(path) {
+                return Some(origin);
+            }
+            path = &path[..path.rfind('.')?];
+        }
+    }
+
+    /// Sets the value at a dotted key path, creating missing maps on the way
+    ///
+    /// A list index may be one past the end to append an item.
+    pub fn set(&mut self, path: &str, value: Value, origin: Origin) -> Result<(), String> {
+        let mut current = &mut self.value;
+        let mut walked = String::new();
+
+        for segment in path.split('.') {
+            if current.is_null() {
+                *current = Value::Mapping(serde_yaml::Mapping::new());
+            }
+            current = match current {
+                Value::Mapping(mapping) => mapping
+                    .entry(Value::String(segment.to_string()))
+                    .or_insert(Value::Null),
+                Value::Sequence(sequence) => {
+                    let index: usize = segment
+                        .parse()
+                        .map_err(|_| format!("{} is a list", display_path(&walked)))?;
+                    if index == sequence.len() {
+                        sequence.push(Value::Null);
+                    }
+                    let len = sequence.len();
+                    sequence.get_mut(index).ok_or_else(|| {
+                        format!("{} has only {} items", display_path(&walked), len)
+                    })?
+                }
+                _ => return Err(format!("{} is not a map or list", display_path(&walked))),
+            };
+            walked = join(&walked, segment);
+        }
+        *current = value;
+
+        // Values below the replaced one no longer come from where they did
+        let prefix = format!("{}.", path);
+        self.origins
+            .retain(|key, _| key != path && !key.starts_with(&prefix));
+        self.origins.insert(path.to_string(), origin);
+        Ok(())
+    }
+
+    /// Formats the merged configuration as YAML, each value followed by a
+    /// comment naming the file and line it came from
+    pub fn to_annotated_yaml(&self) -> String {
+        let mut out = String::new();
+        match &self.value {
+            Value::Mapping(mapping) if !mapping.is_empty() => {
+                self.write_mapping(mapping, "", 0, None, &mut out)
+            }
+            value => out.push_str(&self.annotated_scalar(value, "")),
+        }
+        out
+    }
+
+    /// Writes the entries of a mapping indented by `indent`, the first one
+    /// prefixed with `lead` instead when it continues a sequence item
+    fn write_mapping(
+        &self,
+        mapping: &serde_yaml::Mapping,
+        path: &str,
+        indent: usize,
+        lead: Option<String>,
+        out: &mut String,
+    ) {
+        let mut lead = lead;
+        for (key, value) in mapping {
+            let key = key_string(key).unwrap_or_default();
+            let child_path = join(path, &key);
+            let prefix = lead.take().unwrap_or_else(|| " ".repeat(indent));
+            out.push_str(&format!("{}{}:", prefix, scalar_text(&Value::String(key))));
+
+            match value {
+                Value::Mapping(child) if !child.is_empty() => {
+                    out.push('\n');
+                    self.write_mapping(child, &child_path, indent + 2, None, out);
+                }
+                Value::Sequence(child) if !child.is_empty() => {
+                    out.push('\n');
+                    self.write_sequence(child, &child_path, indent + 2, out);
+                }
+                value => out.push_str(&format!(" {}", self.annotated_scalar(value, &child_path))),
+            }
+        }
+    }
+
+    /// Writes the items of a sequence indented by `indent`
+    fn write_sequence(&self, sequence: &[Value], path: &str, indent: usize, out: &mut String) {
+        for (i, value) in sequence.iter().enumerate() {
+            let child_path = join(path, &i.to_string());
+            let lead = format!("{}- ", " ".repeat(indent));
+
+            match value {
+                Value::Mapping(child) if !child.is_empty() => {
+                    self.write_mapping(child, &child_path, indent + 2, Some(lead), out)
+                }
+                Value::Sequence(child) if !child.is_empty() => {
+                    out.push_str(lead.trim_end());
+                    out.push('\n');
+                    self.write_sequence(child, &child_path, indent + 2, out);
+                }
+                value => {
+                    out.push_str(&lead);
+                    out.push_str(&self.annotated_scalar(value, &child_path));
+                }
+            }
+        }
+    }
+
+    /// Formats a scalar followed by its origin and a line break
+    fn annotated_scalar(&self, value: &Value, path: &str) -> String {
+        match self.origin(path) {
+            Some(origin) => format!("{}  # {}\n", scalar_text(value), origin),
+            None => format!("{}\n", scalar_text(value)),
+        }
+    }
+}
+
+/// Loads a configuration file and merges in the files it extends and includes
+///
+/// A file's `extends` base is applied first, then each `include` in order,
+/// then the file's own keys. Maps are merged key by key; any other value,
+/// including lists, replaces the inherited one unless its key ends in `+`,
+/// in which case the list is appended to the inherited one.
+///
+/// # Arguments
+/// * `path` - Path to the configuration file, or `-` to read standard input
+/// * `format` - Format of that file, detected from its extension if not set.
+///   Extended and included files are always detected from their extension.
+pub fn resolve(path: &str, format: Option<ConfigFormat>) -> Result<Resolved, DeployError> {
+    let mut chain = Vec::new();
+    let mut node = load_node(Path::new(path), format, &mut chain)?;
+    node.strip_append_markers();
+
+    let mut origins = BTreeMap::new();
+    node.collect_origins(String::new(), &mut origins);
+
+    Ok(Resolved {
+        path: if path == source::STDIN {
+            STDIN_NAME.to_string()
+        } else {
+            path.to_string()
+        },
+        value: node.into_value(),
+        origins,
+    })
+}
+
+/// A configuration value annotated with where it was defined
+#[derive(Debug, Clone)]
+struct Node {
+    kind: NodeKind,
+    origin: Option<Origin>,
+}
+
+#[derive(Debug, Clone)]
+enum NodeKind {
+    Map(IndexMap<String, Node>),
+    Seq(Vec<Node>),
+    Scalar(Value),
+}
+
+/// Reads a file and resolves its `extends` and `include` keys
+///
+/// `chain` holds the canonical paths of the files currently being loaded,
+/// so a file that (indirectly) includes itself is detected.
+fn load_node(
+    path: &Path,
+    format: Option<ConfigFormat>,
+    chain: &mut Vec<PathBuf>,
+) -> Result<Node, DeployError> {
+    let (display, canonical, contents) = if path == Path::new(source::STDIN) {
+        let contents =
+            io::read_to_string(io::stdin()).map_err(|e| DeployError::ConfigUnreadable {
+                path: STDIN_NAME.to_string(),
+                message: e.to_string(),
+            })?;
+        (STDIN_NAME.to_string(), path.to_path_buf(), contents)
+    } else {
+        let display = path.display().to_string();
+        if !path.exists() {
+            return Err(DeployError::ConfigNotFound(display));
+        }
+        let unreadable = |e: io::Error| DeployError::ConfigUnreadable {
+            path: display.clone(),
+            message: e.to_string(),
+        };
+        let contents = fs::read_to_string(path).map_err(unreadable)?;
+        let canonical = path.canonicalize().map_err(unreadable)?;
+        (display, canonical, contents)
+    };
+
+    if chain.contains(&canonical) {
+        let cycle: Vec<String> = chain
+            .iter()
+            .skip_while(|p| **p != canonical)
+            .chain(std::iter::once(&canonical))
+            .map(|p| p.display().to_string())
+            .collect();
+        return Err(DeployError::ParseError {
+            path: display,
+            message: format!("Include cycle: {}", cycle.join(" -> ")),
+        });
+    }
+
+    let format = ConfigFormat::detect(path, format);
+    let value: Value =
+        format
+            .deserialize(&contents, |_| {})
+            .map_err(|message| DeployError::ParseError {
+                path: display.clone(),
+                message,
+            })?;
+
+    let locator = KeyLocator::locate(&contents, format);
+    let mut own = Node::from_value(value, &display, &locator, String::new())?;
+
+    let base_dir = path.parent().unwrap_or(Path::new(""));
+    let (extends, includes) = own.take_references(&display)?;
+
+    chain.push(canonical);
+    let mut merged = Node::empty_map();
+    if let Some(extends) = extends {
+        merged.merge(load_node(&base_dir.join(extends), None, chain)?)?;
+    }
+    for include in includes {
+        merged.merge(load_node(&base_dir.join(include), None, chain)?)?;
+    }
+    chain.pop();
+
+    merged.merge(own)?;
+    Ok(merged)
+}
+
+impl Node {
+    /// Returns an empty map without an origin
+    fn empty_map() -> Self {
+        Node {
+            kind: NodeKind::Map(IndexMap::new()),
+            origin: None,
+        }
+    }
+
+    /// Converts a parsed value, taking origins from the keys' positions in `file`
+    fn from_value(
+        value: Value,
+        file: &str,
+        locator: &KeyLocator,
+        path: String,
+    ) -> Result<Self, DeployError> {
+        let origin = Some(Origin {
+            file: file.to_string(),
+            position: locator.find(&path),
+        });
+
+        let kind = match value {
+            Value::Mapping(mapping) => {
+                let mut map = IndexMap::new();
+                for (key, value) in mapping {
+                    let key = key_string(&key).ok_or_else(|| DeployError::ParseError {
+                        path: file.to_string(),
+                        message: format!("Unsupported key {:?} in '{}'", key, path),
+                    })?;
+                    let child_path = join(&path, &key);
+                    map.insert(key, Node::from_value(value, file, locator, child_path)?);
+                }
+                NodeKind::Map(map)
+            }
+            Value::Sequence(sequence) => NodeKind::Seq(
+                sequence
+                    .into_iter()
+                    .enumerate()
+                    .map(|(i, value)| {
+                        Node::from_value(value, file, locator, join(&path, &i.to_string()))
+                    })
+                    .collect::<Result<_, _>>()?,
+            ),
+            Value::Tagged(tagged) => return Node::from_value(tagged.value, file, locator, path),
+            scalar => NodeKind::Scalar(scalar),
+        };
+
+        Ok(Node { kind, origin })
+    }
+
+    /// Removes the top-level `extends` and `include` keys and returns their file names
+    fn take_references(
+        &mut self,
+        file: &str,
+    ) -> Result<(Option<String>, Vec<String>), DeployError> {
+        let NodeKind::Map(map) = &mut self.kind else {
+            return Ok((None, Vec::new()));
+        };
+
+        let invalid = |key: &str| DeployError::ParseError {
+            path: file.to_string(),
+            message: format!("'{}' must be a file path or a list of file paths", key),
+        };
+
+        let extends = match map.shift_remove(EXTENDS_KEY).map(|node| node.kind) {
+            None => None,
+            Some(NodeKind::Scalar(Value::String(path))) => Some(path),
+            Some(_) => return Err(invalid(EXTENDS_KEY)),
+        };
+
+        let includes = match map.shift_remove(INCLUDE_KEY).map(|node| node.kind) {
+            None => Vec::new(),
+            Some(NodeKind::Scalar(Value::String(path))) => vec![path],
+            Some(NodeKind::Seq(items)) => items
+                .into_iter()
+                .map(|item| match item.kind {
+                    NodeKind::Scalar(Value::String(path)) => Ok(path),
+                    _ => Err(invalid(INCLUDE_KEY)),
+                })
+                .collect::<Result<_, _>>()?,
+            Some(_) => return Err(invalid(INCLUDE_KEY)),
+        };
+
+        Ok((extends, includes))
+    }
+
+    /// Merges `overlay` on top of this node
+    ///
+    /// Append markers whose list isn't defined yet are kept, so a fragment
+    /// can append to a list defined by the file that includes it.
+    fn merge(&mut self, overlay: Node) -> Result<(), DeployError> {
+        let (NodeKind::Map(base), NodeKind::Map(_)) = (&mut self.kind, &overlay.kind) else {
+            *self = overlay;
+            return Ok(());
+        };
+        let NodeKind::Map(overlay_map) = overlay.kind else {
+            unreachable!("Overlay is a map");
+        };
+        if self.origin.is_none() {
+            self.origin = overlay.origin;
+        }
+
+        for (key, value) in overlay_map {
+            if let Some(name) = key.strip_suffix(APPEND_SUFFIX).filter(|n| !n.is_empty()) {
+                let NodeKind::Seq(items) = value.kind else {
+                    let file = value.origin.map(|o| o.file).unwrap_or_default();
+                    return Err(DeployError::ParseError {
+                        path: file,
+                        message: format!("'{}' must be a list to be appended", key),
+                    });
+                };
+
+                let target = if base.contains_key(name) {
+                    name
+                } else {
+                    key.as_str()
+                };
+                match base.get_mut(target) {
+                    Some(Node {
+                        kind: NodeKind::Seq(existing),
+                        ..
+                    }) => existing.extend(items),
+                    _ => {
+                        base.insert(
+                            key,
+                            Node {
+                                kind: NodeKind::Seq(items),
+                                origin: value.origin,
+                            },
+                        );
+                    }
+                }
+                continue;
+            }
+
+            base.shift_remove(&format!("{}{}", key, APPEND_SUFFIX));
+            match base.get_mut(&key) {
+                Some(existing) => existing.merge(value)?,
+                None => {
+                    base.insert(key, value);
+                }
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Turns append markers that had nothing to append to into plain keys
+    fn strip_append_markers(&mut self) {
+        match &mut self.kind {
+            NodeKind::Map(map) => {
+                *map = std::mem::take(map)
+                    .into_iter()
+                    .map(|(key, mut node)| {
+                        node.strip_append_markers();
+                        match key.strip_suffix(APPEND_SUFFIX) {
+                            Some(name) if !name.is_empty() => (name.to_string(), node),
+                            _ => (key, node),
+                        }
+                    })
+                    .collect();
+            }
+            NodeKind::Seq(items) => items.iter_mut().for_each(Node::strip_append_markers),
+            NodeKind::Scalar(_) => {}
+        }
+    }
+
+    /// Records the origin of this node and all nodes below it
+    fn collect_origins(&self, path: String, origins: &mut BTreeMap<String, Origin>) {
+        match &self.kind {
+            NodeKind::Map(map) => {
+                for (key, node) in map {
+                    node.collect_origins(join(&path, key), origins);
+                }
+            }
+            NodeKind::Seq(items) => {
+                for (i, node) in items.iter().enumerate() {
+                    node.collect_origins(join(&path, &i.to_string()), origins);
+                }
+            }
+            NodeKind::Scalar(_) => {}
+        }
+        if let Some(origin) = &self.origin {
+            origins.insert(path, origin.clone());
+        }
+    }
+
+    /// Converts back to a plain value
+    fn into_value(self) -> Value {
+        match self.kind {
+            NodeKind::Map(map) => Value::Mapping(
+                map.into_iter()
+                    .map(|(key, node)| (Value::String(key), node.into_value()))
+                    .collect(),
+            ),
+            NodeKind::Seq(items) => {
+                Value::Sequence(items.into_iter().map(Node::into_value).collect())
+            }
+            NodeKind::Scalar(value) => value,
+        }
+    }
+}
+
+/// Returns a map key as a string, if it is a scalar
+fn key_string(key: &Value) -> Option<String> {
+    match key {
+        Value::String(s) => Some(s.clone()),
+        Value::Number(n) => Some(n.to_string()),
+        Value::Bool(b) => Some(b.to_string()),
+        _ => None,
+    }
+}
+
+/// Returns a dotted key path for messages, naming the top level if it is empty
+fn display_path(path: &str) -> &str {
+    if path.is_empty() {
+        "the configuration"
+    } else {
+        path
+    }
+}
+
+/// Formats a scalar or empty collection as a single line of YAML
+fn scalar_text(value: &Value) -> String {
+    let yaml = serde_yaml::to_string(value).unwrap_or_default();
+    let yaml = yaml.trim_end();
+    if yaml.contains('\n') {
+        // Multi-line strings become block scalars; JSON strings are valid YAML
+        serde_json::to_string(value).unwrap_or_default()
+    } else {
+        yaml.to_string()
+    }
+}
+
+/// Appends a segment to a dotted key path
+fn join(parent: &str, segment: &str) -> String {
+    if parent.is_empty() {
+        segment.to_string()
+    } else {
+        format!("{}.{}", parent, segment)
+    }
+}
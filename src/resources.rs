@@ -0,0 +1,362 @@
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Component, Path, PathBuf};
+
+use crate::condition::{self, Context};
+use crate::config::{Config, Resource, ResourcesDir};
+use crate::error::DeployError;
+use crate::source;
+
+/// Header written at the top of generated lockfiles
+const LOCKFILE_HEADER: &str = "# Generated by `ud resources lock`. Do not edit by hand.\n";
+
+/// Recorded SHA-256 hashes of resource files
+#[derive(Debug, Default, Deserialize, Serialize)]
+pub struct Lockfile {
+    /// Source path relative to the configuration directory, e.g.
+    /// `resources/app.env`, mapped to its hex-encoded SHA-256
+    #[serde(default)]
+    pub resources: BTreeMap<String, String>,
+
+    /// Directory of the configuration file that source paths are relative to
+    #[serde(skip)]
+    dir: PathBuf,
+}
+
+impl Lockfile {
+    /// Creates an empty lockfile for a configuration file
+    pub fn new(config_path: &str) -> Self {
+        Lockfile {
+            resources: BTreeMap::new(),
+            dir: config_dir(config_path).to_path_buf(),
+        }
+    }
+
+    /// Returns the entry name of a resource source file
+    ///
+    /// Sources are identified by their path relative to the configuration
+    /// directory, so releases with different `resources_dir` can use the
+    /// same file name for different files.
+    pub fn key(&self, source: &Path) -> String {
+        let relative = source.strip_prefix(&self.dir).unwrap_or(source);
+        relative
+            .components()
+            .filter(|component| !matches!(component, Component::CurDir))
+            .map(|component| component.as_os_str().to_string_lossy())
+            .collect::<Vec<_>>()
+            .join("/")
+    }
+
+    /// Loads the lockfile belonging to a configuration file, if one exists
+    pub fn load(config_path: &str) -> Result<Option<Self>, DeployError> {
+        let path = lockfile_path(config_path);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        let contents = fs::read_to_string(&path).map_err(|e| {
+            DeployError::Resource(format!("Could not read lockfile {}: {}", path.display(), e))
+        })?;
+
+        let mut lockfile: Lockfile = serde_yaml::from_str(&contents).map_err(|e| {
+            DeployError::Resource(format!(
+                "Failed to parse lockfile {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        lockfile.dir = config_dir(config_path).to_path_buf();
+
+        Ok(Some(lockfile))
+    }
+
+    /// Writes the lockfile next to the configuration file
+    pub fn save(&self, config_path: &str) -> Result<PathBuf, DeployError> {
+        let path = lockfile_path(config_path);
+        let yaml = serde_yaml::to_string(self)
+            .map_err(|e| DeployError::Resource(format!("Failed to serialize lockfile: {}", e)))?;
+        fs::write(&path, format!("{}{}", LOCKFILE_HEADER, yaml)).map_err(|e| {
+            DeployError::Resource(format!(
+                "Could not write lockfile {}: {}",
+                path.display(),
+                e
+            ))
+        })?;
+        Ok(path)
+    }
+}
+
+/// Returns the lockfile path for a configuration file (`deploy.yml` -> `deploy.lock`)
+pub fn lockfile_path(config_path: &str) -> PathBuf {
+    Path::new(config_path).with_extension("lock")
+}
+
+/// Returns the directory of a configuration file, which resource paths are relative to
+fn config_dir(config_path: &str) -> &Path {
+    Path::new(config_path)
+        .parent()
+        .unwrap_or_else(|| Path::new("."))
+}
+
+/// Returns the resource roots of a configuration file in search order
+pub fn resource_roots(config_path: &str, resources_dir: &ResourcesDir) -> Vec<PathBuf> {
+    let config_dir = config_dir(config_path);
+
+    resources_dir
+        .dirs()
+        .into_iter()
+        .map(|dir| config_dir.join(dir))
+        .collect()
+}
+
+/// Source file of a resource and the root that supplied it
+#[derive(Debug)]
+pub struct ResolvedSource {
+    /// Path to the source file
+    pub path: PathBuf,
+    /// Resource root the file was found in
+    pub root: PathBuf,
+}
+
+/// Finds the first resource root containing a resource's source file
+///
+/// The file must stay within the root it was found in.
+pub fn resolve_source(
+    roots: &[PathBuf],
+    resource: &Resource,
+) -> Result<ResolvedSource, DeployError> {
+    for root in roots {
+        let path = root.join(&resource.file);
+        if path.exists() {
+            validate_path(&path, root)?;
+            return Ok(ResolvedSource {
+                path,
+                root: root.clone(),
+            });
+        }
+    }
+
+    let searched: Vec<String> = roots.iter().map(|r| r.display().to_string()).collect();
+    Err(DeployError::Resource(format!(
+        "Resource '{}' not found in any resources directory (searched: {})",
+        resource.file,
+        searched.join(", ")
+    )))
+}
+
+/// Reads a resource file, naming it if that fails
+pub fn read_resource(path: &Path) -> Result<Vec<u8>, DeployError> {
+    fs::read(path).map_err(|e| {
+        DeployError::Resource(format!("Could not read resource {}: {}", path.display(), e))
+    })
+}
+
+/// Validates that a path doesn't escape its intended base directory
+///
+/// Paths that don't exist yet are checked through their nearest
+/// existing ancestor, so nested destinations can be validated before
+/// their directories are created.
+pub fn validate_path(path: &Path, base: &Path) -> Result<(), DeployError> {
+    let canonical_base = base.canonicalize()?;
+
+    let mut ancestor = path;
+    let canonical_ancestor = loop {
+        let candidate = if ancestor.as_os_str().is_empty() {
+            Path::new(".")
+        } else {
+            ancestor
+        };
+        if let Ok(canonical) = candidate.canonicalize() {
+            break canonical;
+        }
+        ancestor = ancestor
+            .parent()
+            .ok_or_else(|| std::io::Error::new(std::io::ErrorKind::NotFound, "Invalid path"))?;
+    };
+
+    // The part that doesn't exist yet can't be canonicalized, so reject `..` in it
+    let escapes_lexically = path
+        .strip_prefix(ancestor)
+        .map(|rest| rest.components().any(|c| c == Component::ParentDir))
+        .unwrap_or(true);
+
+    if escapes_lexically || !canonical_ancestor.starts_with(&canonical_base) {
+        return Err(DeployError::PathTraversal {
+            path: path.to_path_buf(),
+            base: base.to_path_buf(),
+        });
+    }
+
+    Ok(())
+}
+
+/// Computes the hex-encoded SHA-256 of a byte slice
+pub fn sha256_hex(contents: &[u8]) -> String {
+    hex::encode(Sha256::digest(contents))
+}
+
+/// Verifies resource contents against its declared hash and the lockfile
+///
+/// # Arguments
+/// * `resource` - Resource being copied
+/// * `source` - Path of the resource source file, as resolved by [`resolve_source`]
+/// * `contents` - Contents of the resource source file
+/// * `lockfile` - Lockfile belonging to the configuration, if any
+pub fn verify_checksum(
+    resource: &Resource,
+    source: &Path,
+    contents: &[u8],
+    lockfile: Option<&Lockfile>,
+) -> Result<(), DeployError> {
+    let actual = sha256_hex(contents);
+
+    if let Some(expected) = &resource.sha256 {
+        if !expected.eq_ignore_ascii_case(&actual) {
+            return Err(DeployError::ChecksumMismatch {
+                file: resource.file.clone(),
+                expected: expected.clone(),
+                actual,
+            });
+        }
+    }
+
+    if let Some(lockfile) = lockfile {
+        let key = lockfile.key(source);
+        match lockfile.resources.get(&key) {
+            Some(expected) if !expected.eq_ignore_ascii_case(&actual) => {
+                return Err(DeployError::LockfileMismatch {
+                    file: key,
+                    expected: expected.clone(),
+                    actual,
+                });
+            }
+            Some(_) => {}
+            None => {
+                return Err(DeployError::Resource(format!(
+                    "Resource '{}' is missing from the lockfile. Run `ud resources lock` to add it.",
+                    key
+                )));
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Summarizes how a file's content changed, e.g. `+3 -1 lines`
+///
+/// Lines shared at the start and end of both versions are ignored and
+/// everything in between counts as removed and added.
+pub fn diff_summary(old: &[u8], new: &[u8]) -> String {
+    let (Ok(old), Ok(new)) = (std::str::from_utf8(old), std::str::from_utf8(new)) else {
+        return format!("binary, {} -> {} bytes", old.len(), new.len());
+    };
+
+    let old: Vec<&str> = old.lines().collect();
+    let new: Vec<&str> = new.lines().collect();
+
+    let prefix = old.iter().zip(&new).take_while(|(a, b)| a == b).count();
+    let suffix = old[prefix..]
+        .iter()
+        .rev()
+        .zip(new[prefix..].iter().rev())
+        .take_while(|(a, b)| a == b)
+        .count();
+
+    format!(
+        "+{} -{} lines",
+        new.len() - prefix - suffix,
+        old.len() - prefix - suffix
+    )
+}
+
+/// Hashes all resources of a configuration and writes its lockfile
+///
+/// # Arguments
+/// * `config_path` - Path to the configuration file
+/// * `verbose` - Enable verbose logging
+///
+/// # Returns
+/// Path to the written lockfile
+pub fn lock(config_path: &str, verbose: bool) -> Result<PathBuf, DeployError> {
+    if config_path == source::STDIN {
+        return Err(DeployError::Resource(
+            "Can't write a lockfile for a configuration read from stdin".to_string(),
+        ));
+    }
+
+    let config = Config::load(config_path)?;
+
+    let mut lockfile = Lockfile::new(config_path);
+    for (_, release) in config.release_entries() {
+        let roots = resource_roots(config_path, &release.resources_dir);
+
+        for resource in &release.resources {
+            let source = resolve_source(&roots, resource)?;
+            let contents = read_resource(&source.path)?;
+
+            // A declared hash still has to hold when locking
+            verify_checksum(resource, &source.path, &contents, None)?;
+
+            // Releases share the lockfile; a source used by several is locked once
+            let key = lockfile.key(&source.path);
+            if lockfile.resources.contains_key(&key) {
+                continue;
+            }
+            let hash = sha256_hex(&contents);
+            if verbose {
+                println!("{}  {}", hash, key);
+            }
+            lockfile.resources.insert(key, hash);
+        }
+    }
+
+    lockfile.save(config_path)
+}
+
+/// Prints where each resource would be copied from and to, verifying
+/// checksums along the way, without touching any repository
+pub fn print_plan(
+    config_path: &str,
+    config: &Config,
+    context: &Context,
+) -> Result<(), DeployError> {
+    let roots = resource_roots(config_path, &config.release.resources_dir);
+    let lockfile = Lockfile::load(config_path)?;
+
+    let root_names: Vec<String> = roots.iter().map(|r| r.display().to_string()).collect();
+    println!("Resource roots: {}", root_names.join(", "));
+
+    for resource in &config.release.resources {
+        if let Some(when) = &resource.when {
+            match condition::evaluate(when, context) {
+                Ok(true) => {}
+                Ok(false) => {
+                    println!("  {} skipped (when: {})", resource.file, when);
+                    continue;
+                }
+                Err(e) => println!("  {} depends on a condition: {}", resource.file, e),
+            }
+        }
+
+        let source = resolve_source(&roots, resource)?;
+        let contents = read_resource(&source.path)?;
+        verify_checksum(resource, &source.path, &contents, lockfile.as_ref())?;
+
+        println!(
+            "  {} -> {} (from {}{})",
+            resource.file,
+            resource.destination(),
+            source.root.display(),
+            if resource.encrypted {
+                ", encrypted"
+            } else {
+                ""
+            }
+        );
+    }
+
+    Ok(())
+}
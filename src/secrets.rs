@@ -0,0 +1,221 @@
+use chacha20poly1305::aead::{Aead, AeadCore, KeyInit, OsRng};
+use chacha20poly1305::{ChaCha20Poly1305, Nonce};
+use std::fs;
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use uuid::Uuid;
+
+use crate::error::DeployError;
+
+pub use chacha20poly1305::Key;
+
+/// Magic header identifying files encrypted by `ud`
+const HEADER: &[u8] = b"UD-ENCRYPTED-V1\n";
+
+/// Length of the ChaCha20-Poly1305 nonce in bytes
+const NONCE_LEN: usize = 12;
+
+/// Environment variable holding the hex-encoded key
+pub const KEY_ENV: &str = "UD_SECRETS_KEY";
+
+/// Environment variable holding the path to a key file
+pub const KEY_FILE_ENV: &str = "UD_SECRETS_KEY_FILE";
+
+/// File extension conventionally used for encrypted resources
+pub const ENCRYPTED_EXTENSION: &str = ".enc";
+
+/// Loads the symmetric key used for resource encryption
+///
+/// The key is read from `key_file` if given, otherwise from the
+/// `UD_SECRETS_KEY` or `UD_SECRETS_KEY_FILE` environment variables.
+/// It must be 32 bytes, hex-encoded (e.g. from `openssl rand -hex 32`).
+pub fn load_key(key_file: Option<&Path>) -> Result<Key, DeployError> {
+    let encoded = if let Some(path) = key_file {
+        read_key_file(path)?
+    } else if let Ok(value) = std::env::var(KEY_ENV) {
+        value
+    } else if let Ok(path) = std::env::var(KEY_FILE_ENV) {
+        read_key_file(Path::new(&path))?
+    } else {
+        return Err(DeployError::Secrets(format!(
+            "No secrets key available. Set {} or {}",
+            KEY_ENV, KEY_FILE_ENV
+        )));
+    };
+
+    let bytes = hex::decode(encoded.trim())
+        .map_err(|e| DeployError::Secrets(format!("Secrets key is not valid hex: {}", e)))?;
+
+    if bytes.len() != 32 {
+        return Err(DeployError::Secrets(format!(
+            "Secrets key must be 32 bytes (64 hex characters), got {} bytes",
+            bytes.len()
+        )));
+    }
+
+    Ok(*Key::from_slice(&bytes))
+}
+
+/// Reads a hex-encoded key from a file
+fn read_key_file(path: &Path) -> Result<String, DeployError> {
+    fs::read_to_string(path).map_err(|e| {
+        DeployError::Secrets(format!("Could not read key file {}: {}", path.display(), e))
+    })
+}
+
+/// Encrypts plaintext into the `ud` encrypted file format
+pub fn encrypt(key: &Key, plaintext: &[u8]) -> Result<Vec<u8>, DeployError> {
+    let cipher = ChaCha20Poly1305::new(key);
+    let nonce = ChaCha20Poly1305::generate_nonce(&mut OsRng);
+    let ciphertext = cipher
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| DeployError::Secrets("Encryption failed".to_string()))?;
+
+    let mut output = Vec::with_capacity(HEADER.len() + NONCE_LEN + ciphertext.len());
+    output.extend_from_slice(HEADER);
+    output.extend_from_slice(&nonce);
+    output.extend_from_slice(&ciphertext);
+    Ok(output)
+}
+
+/// Decrypts contents in the `ud` encrypted file format
+pub fn decrypt(key: &Key, contents: &[u8]) -> Result<Vec<u8>, DeployError> {
+    let body = contents.strip_prefix(HEADER).ok_or_else(|| {
+        DeployError::Secrets("File is not encrypted with `ud secrets`".to_string())
+    })?;
+
+    if body.len() < NONCE_LEN {
+        return Err(DeployError::Secrets(
+            "Encrypted file is truncated".to_string(),
+        ));
+    }
+
+    let (nonce, ciphertext) = body.split_at(NONCE_LEN);
+    let cipher = ChaCha20Poly1305::new(key);
+    cipher
+        .decrypt(Nonce::from_slice(nonce), ciphertext)
+        .map_err(|_| {
+            DeployError::Secrets("Decryption failed: wrong key or corrupted file".to_string())
+        })
+}
+
+/// Writes a file readable and writable only by the current user
+pub fn write_private(path: &Path, contents: &[u8]) -> Result<(), DeployError> {
+    write_private_file(path, contents)
+        .map_err(|e| DeployError::Resource(format!("Could not write {}: {}", path.display(), e)))
+}
+
+fn write_private_file(path: &Path, contents: &[u8]) -> std::io::Result<()> {
+    let mut options = fs::OpenOptions::new();
+    options.write(true).create(true).truncate(true);
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::OpenOptionsExt;
+        options.mode(0o600);
+    }
+
+    let mut file = options.open(path)?;
+
+    // The mode only applies on creation, so tighten existing files too
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::PermissionsExt;
+        file.set_permissions(fs::Permissions::from_mode(0o600))?;
+    }
+
+    file.write_all(contents)?;
+    Ok(())
+}
+
+/// Encrypts a file, writing the result to `output`
+pub fn encrypt_file(
+    input: &Path,
+    output: &Path,
+    key_file: Option<&Path>,
+) -> Result<(), DeployError> {
+    let key = load_key(key_file)?;
+    let plaintext = read_file(input)?;
+    fs::write(output, encrypt(&key, &plaintext)?).map_err(|e| {
+        DeployError::Secrets(format!("Could not write {}: {}", output.display(), e))
+    })?;
+    Ok(())
+}
+
+/// Decrypts a file, returning its plaintext
+pub fn decrypt_file(input: &Path, key_file: Option<&Path>) -> Result<Vec<u8>, DeployError> {
+    let key = load_key(key_file)?;
+    let contents = read_file(input)?;
+    decrypt(&key, &contents)
+}
+
+/// Reads a file to encrypt or decrypt, naming it if that fails
+fn read_file(path: &Path) -> Result<Vec<u8>, DeployError> {
+    fs::read(path)
+        .map_err(|e| DeployError::Secrets(format!("Could not read {}: {}", path.display(), e)))
+}
+
+/// Decrypts a file into a private temporary file, opens it in `$EDITOR`
+/// and re-encrypts it if it was changed
+///
+/// # Returns
+/// Whether the file was changed
+pub fn edit_file(input: &Path, key_file: Option<&Path>) -> Result<bool, DeployError> {
+    let key = load_key(key_file)?;
+    let plaintext = if input.exists() {
+        decrypt(&key, &read_file(input)?)?
+    } else {
+        Vec::new()
+    };
+
+    let temp_path = std::env::temp_dir().join(format!("ud-secret-{}", Uuid::new_v4()));
+    write_private(&temp_path, &plaintext)?;
+
+    let result = run_editor(&temp_path).and_then(|_| {
+        let edited = read_file(&temp_path)?;
+        if edited == plaintext {
+            return Ok(false);
+        }
+        fs::write(input, encrypt(&key, &edited)?).map_err(|e| {
+            DeployError::Secrets(format!("Could not write {}: {}", input.display(), e))
+        })?;
+        Ok(true)
+    });
+
+    // Never leave the plaintext behind, whatever the outcome
+    let _ = fs::remove_file(&temp_path);
+
+    result
+}
+
+/// Opens a file in the user's editor and waits for it to exit
+fn run_editor(path: &Path) -> Result<(), DeployError> {
+    let editor = std::env::var("VISUAL")
+        .or_else(|_| std::env::var("EDITOR"))
+        .unwrap_or_else(|_| "vi".to_string());
+
+    // Allow editors configured with arguments, e.g. "code --wait"
+    let mut parts = editor.split_whitespace();
+    let program = parts
+        .next()
+        .ok_or_else(|| DeployError::Secrets("Editor command is empty".to_string()))?;
+
+    let status = Command::new(program).args(parts).arg(path).status()?;
+    if !status.success() {
+        return Err(DeployError::Secrets(format!(
+            "Editor '{}' exited with code: {}",
+            editor,
+            status.code().unwrap_or(1)
+        )));
+    }
+
+    Ok(())
+}
+
+/// Returns the default output path when encrypting a file (`secrets.env` -> `secrets.env.enc`)
+pub fn encrypted_path(input: &Path) -> PathBuf {
+    let mut name = input.as_os_str().to_owned();
+    name.push(ENCRYPTED_EXTENSION);
+    PathBuf::from(name)
+}
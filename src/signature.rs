@@ -0,0 +1,219 @@
+use std::path::Path;
+
+use crate::config::SignatureCheck;
+use crate::error::DeployError;
+use crate::git;
+
+/// Returns the fingerprints of the key that made a good signature
+///
+/// GPG reports the signing key and its primary key in its `VALIDSIG` status
+/// line, and ssh-keygen the `SHA256:` fingerprint of the key. A GPG
+/// signature only counts with a `GOODSIG` line: bad signatures, expired
+/// signatures and signatures from expired or revoked keys don't.
+///
+/// # Arguments
+/// * `status` - Output of [`git::signature_status`]
+pub fn good_signature_fingerprints(status: &str) -> Vec<String> {
+    let mut fingerprints = Vec::new();
+    let mut good = false;
+
+    for line in status.lines() {
+        let keyword = line
+            .strip_prefix("[GNUPG:] ")
+            .and_then(|rest| rest.split_whitespace().next());
+        match keyword {
+            Some("BADSIG" | "ERRSIG" | "EXPSIG" | "EXPKEYSIG" | "REVKEYSIG") => return Vec::new(),
+            Some("GOODSIG") => good = true,
+            _ => {}
+        }
+
+        if let Some(fields) = line.strip_prefix("[GNUPG:] VALIDSIG ") {
+            let fields: Vec<&str> = fields.split_whitespace().collect();
+            // The signing key comes first and the primary key tenth
+            for index in [0, 9] {
+                if let Some(fingerprint) = fields.get(index) {
+                    fingerprints.push(normalize(fingerprint));
+                }
+            }
+        } else if line.starts_with("Good \"git\" signature") {
+            if let Some((_, key)) = line.rsplit_once(" key ") {
+                fingerprints.push(normalize(key));
+            }
+        }
+    }
+
+    // Without `GOODSIG`, a `VALIDSIG` line alone doesn't make a signature good
+    if !good {
+        fingerprints.retain(|fingerprint| fingerprint.starts_with("SHA256:"));
+    }
+
+    fingerprints.dedup();
+    fingerprints
+}
+
+/// Normalizes a fingerprint for comparison
+///
+/// GPG fingerprints are hexadecimal and often written in groups, so spaces
+/// are removed and letters uppercased. SSH fingerprints are base64 and keep
+/// their case.
+pub fn normalize(fingerprint: &str) -> String {
+    let fingerprint: String = fingerprint.split_whitespace().collect();
+    if fingerprint.starts_with("SHA256:") {
+        fingerprint
+    } else {
+        fingerprint.to_ascii_uppercase()
+    }
+}
+
+/// Returns the first fingerprint of a good signature that is allowed
+///
+/// # Arguments
+/// * `fingerprints` - Fingerprints from [`good_signature_fingerprints`]
+/// * `allowed` - Normalized fingerprints of the trusted keys
+fn allowed_fingerprint<'a>(fingerprints: &'a [String], allowed: &[String]) -> Option<&'a str> {
+    fingerprints
+        .iter()
+        .find(|fingerprint| allowed.contains(fingerprint))
+        .map(String::as_str)
+}
+
+/// Checks that `HEAD` of a checkout is signed by an allowed key
+///
+/// # Arguments
+/// * `repo_path` - Checkout being deployed
+/// * `check` - Whether the commit or a tag pointing to it must be signed
+/// * `allowed` - Fingerprints of the trusted keys
+/// * `verbose` - Whether to print the signature status
+pub fn verify(
+    repo_path: &Path,
+    check: SignatureCheck,
+    allowed: &[String],
+    verbose: bool,
+) -> Result<(), DeployError> {
+    let allowed: Vec<String> = allowed.iter().map(|f| normalize(f)).collect();
+    let commit = git::head_commit(repo_path)?;
+    let short = git::short_sha(&commit);
+
+    let objects = match check {
+        SignatureCheck::Commit => vec![commit.clone()],
+        SignatureCheck::Tag => {
+            let tags = git::tags_at(repo_path, &commit)?;
+            if tags.is_empty() {
+                return Err(DeployError::UntrustedSignature(format!(
+                    "no tag points to commit {}",
+                    short
+                )));
+            }
+            tags
+        }
+    };
+
+    let mut untrusted = Vec::new();
+    for object in &objects {
+        let status = git::signature_status(repo_path, &check.to_string(), object)?;
+        if verbose {
+            print!("{}", status);
+        }
+        let name = match check {
+            SignatureCheck::Commit => format!("commit {}", short),
+            SignatureCheck::Tag => format!("tag {}", object),
+        };
+
+        let fingerprints = good_signature_fingerprints(&status);
+        if let Some(fingerprint) = allowed_fingerprint(&fingerprints, &allowed) {
+            println!("Verified signature of {} by {}", name, fingerprint);
+            return Ok(());
+        }
+        untrusted.push(match fingerprints.first() {
+            Some(fingerprint) => {
+                format!("{} is signed by {}, which isn't allowed", name, fingerprint)
+            }
+            None if status.trim().is_empty() => format!("{} is not signed", name),
+            None => format!("{} has no valid signature", name),
+        });
+    }
+
+    Err(DeployError::UntrustedSignature(untrusted.join("; ")))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SIGNING_KEY: &str = "0123456789ABCDEF0123456789ABCDEF01234567";
+    const PRIMARY_KEY: &str = "89ABCDEF0123456789ABCDEF0123456789ABCDEF";
+
+    /// Returns GPG status output with `result` in place of the `GOODSIG` line
+    fn gpg_status(result: &str) -> String {
+        format!(
+            "[GNUPG:] NEWSIG\n\
+             [GNUPG:] {} 89ABCDEF01234567 Test <test@example.com>\n\
+             [GNUPG:] VALIDSIG {} 2024-02-29 1709209805 0 4 0 22 10 00 {}\n\
+             [GNUPG:] TRUST_UNDEFINED 0 pgp\n",
+            result, SIGNING_KEY, PRIMARY_KEY
+        )
+    }
+
+    #[test]
+    fn accepts_good_gpg_signature() {
+        assert_eq!(
+            good_signature_fingerprints(&gpg_status("GOODSIG")),
+            vec![SIGNING_KEY.to_string(), PRIMARY_KEY.to_string()]
+        );
+    }
+
+    #[test]
+    fn rejects_bad_signature_next_to_validsig() {
+        let status = format!(
+            "[GNUPG:] BADSIG 89ABCDEF01234567 Test\n{}",
+            gpg_status("GOODSIG")
+        );
+        assert!(good_signature_fingerprints(&status).is_empty());
+    }
+
+    #[test]
+    fn rejects_expired_and_revoked_keys() {
+        for result in ["EXPKEYSIG", "EXPSIG", "REVKEYSIG", "ERRSIG"] {
+            assert!(
+                good_signature_fingerprints(&gpg_status(result)).is_empty(),
+                "{}",
+                result
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_validsig_without_goodsig() {
+        let status = format!(
+            "[GNUPG:] VALIDSIG {} 2024-02-29 1709209805 0 4 0 22 10 00 {}\n",
+            SIGNING_KEY, PRIMARY_KEY
+        );
+        assert!(good_signature_fingerprints(&status).is_empty());
+    }
+
+    #[test]
+    fn matches_only_allowed_fingerprints() {
+        let fingerprints = good_signature_fingerprints(&gpg_status("GOODSIG"));
+
+        let other = vec![normalize(
+            "FEDC BA98 7654 3210 FEDC BA98 7654 3210 FEDC BA98",
+        )];
+        assert_eq!(allowed_fingerprint(&fingerprints, &other), None);
+
+        // Allowed keys may be written in groups and lowercase
+        let primary = vec![normalize("89abcdef 01234567 89abcdef 01234567 89abcdef")];
+        assert_eq!(
+            allowed_fingerprint(&fingerprints, &primary),
+            Some(PRIMARY_KEY)
+        );
+    }
+
+    #[test]
+    fn reads_ssh_signature_fingerprint() {
+        let status = "Good \"git\" signature for test@example.com with ED25519 key SHA256:AbCdEf0123456789+/xyz\n";
+        assert_eq!(
+            good_signature_fingerprints(status),
+            vec!["SHA256:AbCdEf0123456789+/xyz".to_string()]
+        );
+    }
+}
@@ -0,0 +1,96 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use uuid::Uuid;
+
+use crate::error::DeployError;
+use crate::git;
+use crate::resources;
+
+/// Location that reads the configuration from standard input
+pub const STDIN: &str = "-";
+
+/// Prefix of locations that load the configuration out of a git repository
+const GIT_PREFIX: &str = "git+";
+
+/// A configuration file made available on the local filesystem
+///
+/// Besides plain paths, a location can be `-` for standard input or
+/// `git+<url>#<ref>:<path>` for a file in a repository. Repositories are
+/// checked out into a temporary directory that is removed when the source
+/// is dropped, so resources and includes next to the file are found as if
+/// the repository had been cloned by hand.
+#[derive(Debug)]
+pub struct ConfigSource {
+    path: String,
+    checkout: Option<PathBuf>,
+}
+
+impl ConfigSource {
+    /// Opens a configuration location
+    ///
+    /// # Arguments
+    /// * `location` - Path, `-` or `git+<url>#<ref>:<path>`
+    /// * `verbose` - Enable verbose logging
+    pub fn open(location: &str, verbose: bool) -> Result<Self, DeployError> {
+        let Some(spec) = location.strip_prefix(GIT_PREFIX) else {
+            return Ok(ConfigSource {
+                path: location.to_string(),
+                checkout: None,
+            });
+        };
+
+        let (repo_url, reference, file) = parse_git_spec(spec).ok_or_else(|| {
+            DeployError::ValidationError(format!(
+                "Invalid config location '{}': expected git+<url>#<ref>:<path>",
+                location
+            ))
+        })?;
+
+        let checkout = std::env::temp_dir().join(format!("ud-config-{}", Uuid::new_v4()));
+        // Created before fetching so a failed fetch is cleaned up as well
+        let source = ConfigSource {
+            path: checkout.join(file).to_string_lossy().into_owned(),
+            checkout: Some(checkout.clone()),
+        };
+
+        git::fetch_snapshot(repo_url, reference, &checkout, verbose)?;
+
+        let path = Path::new(&source.path);
+        resources::validate_path(path, &checkout)?;
+        if !path.is_file() {
+            return Err(DeployError::ConfigNotFound(location.to_string()));
+        }
+
+        Ok(source)
+    }
+
+    /// Returns the local path of the configuration file, or `-` for standard input
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+}
+
+impl Drop for ConfigSource {
+    fn drop(&mut self) {
+        if let Some(checkout) = &self.checkout {
+            let _ = fs::remove_dir_all(checkout);
+        }
+    }
+}
+
+/// Splits `<url>#<ref>:<path>` into its parts; an empty ref means the default branch
+fn parse_git_spec(spec: &str) -> Option<(&str, &str, &str)> {
+    let (repo_url, rest) = spec.rsplit_once('#')?;
+    let (reference, file) = rest.split_once(':')?;
+
+    if repo_url.is_empty() || file.is_empty() {
+        return None;
+    }
+
+    let reference = if reference.is_empty() {
+        "HEAD"
+    } else {
+        reference
+    };
+    Some((repo_url, reference, file))
+}
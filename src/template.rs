@@ -0,0 +1,55 @@
+use std::collections::BTreeMap;
+
+/// Replaces `${name}` placeholders in a text
+///
+/// # Arguments
+/// * `text` - Text containing placeholders; `$${` is a literal `${`
+/// * `values` - Value of every placeholder name
+///
+/// # Returns
+/// The expanded text, or a message naming the first unknown or unterminated placeholder
+pub fn expand(text: &str, values: &BTreeMap<&str, String>) -> Result<String, String> {
+    let mut expanded = String::new();
+    let mut rest = text;
+
+    while let Some(start) = rest.find("${") {
+        // `$${` escapes a placeholder
+        if rest[..start].ends_with('$') {
+            expanded.push_str(&rest[..start - 1]);
+            expanded.push_str("${");
+            rest = &rest[start + 2..];
+            continue;
+        }
+
+        expanded.push_str(&rest[..start]);
+        let after = &rest[start + 2..];
+        let end = after
+            .find('}')
+            .ok_or_else(|| format!("Unterminated placeholder in '{}'", text))?;
+        let name = after[..end].trim();
+        let value = values.get(name).ok_or_else(|| {
+            let known: Vec<&str> = values.keys().copied().collect();
+            format!(
+                "Unknown placeholder '${{{}}}' in '{}' (available: {})",
+                name,
+                text,
+                known.join(", ")
+            )
+        })?;
+        expanded.push_str(value);
+        rest = &after[end + 1..];
+    }
+
+    expanded.push_str(rest);
+    Ok(expanded)
+}
+
+/// Checks that a text only uses the given placeholder names
+///
+/// # Arguments
+/// * `text` - Text containing placeholders
+/// * `names` - Placeholder names that will have a value
+pub fn check(text: &str, names: &[&str]) -> Result<(), String> {
+    let values = names.iter().map(|&name| (name, String::new())).collect();
+    expand(text, &values).map(|_| ())
+}
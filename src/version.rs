@@ -0,0 +1,328 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::config::VersionConfig;
+use crate::error::DeployError;
+use crate::git::{self, CommitInfo};
+use crate::notes::ConventionalCommit;
+use crate::template;
+
+/// Environment variable holding the version for the tool
+pub const ENV_VAR: &str = "UD_VERSION";
+
+/// A `MAJOR.MINOR.PATCH` version
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Version {
+    pub major: u64,
+    pub minor: u64,
+    pub patch: u64,
+}
+
+impl Version {
+    /// Returns the next version for a bump, resetting the lower components
+    pub fn bump(self, bump: Bump) -> Version {
+        match bump {
+            Bump::Major => Version {
+                major: self.major + 1,
+                minor: 0,
+                patch: 0,
+            },
+            Bump::Minor => Version {
+                minor: self.minor + 1,
+                patch: 0,
+                ..self
+            },
+            Bump::Patch => Version {
+                patch: self.patch + 1,
+                ..self
+            },
+        }
+    }
+}
+
+impl FromStr for Version {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parts: Vec<&str> = s.split('.').collect();
+        let [major, minor, patch] = parts.as_slice() else {
+            return Err(format!("Expected MAJOR.MINOR.PATCH, got '{}'", s));
+        };
+        let parse = |part: &str| {
+            part.parse::<u64>()
+                .map_err(|_| format!("Expected MAJOR.MINOR.PATCH, got '{}'", s))
+        };
+        Ok(Version {
+            major: parse(major)?,
+            minor: parse(minor)?,
+            patch: parse(patch)?,
+        })
+    }
+}
+
+impl fmt::Display for Version {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{}.{}", self.major, self.minor, self.patch)
+    }
+}
+
+/// Version component to increase
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Bump {
+    Patch,
+    Minor,
+    Major,
+}
+
+impl Bump {
+    /// Names accepted by [`Bump::from_str`]
+    pub const NAMES: &'static [&'static str] = &["major", "minor", "patch"];
+
+    /// Infers the bump from conventional commits: breaking changes are major,
+    /// features minor and anything else a patch
+    ///
+    /// # Returns
+    /// The bump, or `None` if there are no commits
+    pub fn infer(commits: &[CommitInfo]) -> Option<Bump> {
+        commits
+            .iter()
+            .map(|commit| match ConventionalCommit::parse(&commit.subject) {
+                Some(parsed) if parsed.breaking => Bump::Major,
+                Some(parsed) if parsed.kind == "feat" => Bump::Minor,
+                _ => Bump::Patch,
+            })
+            .max()
+    }
+}
+
+impl FromStr for Bump {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "major" => Ok(Bump::Major),
+            "minor" => Ok(Bump::Minor),
+            "patch" => Ok(Bump::Patch),
+            _ => Err(format!(
+                "Unknown bump '{}' (expected one of: {})",
+                s,
+                Bump::NAMES.join(", ")
+            )),
+        }
+    }
+}
+
+impl fmt::Display for Bump {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Bump::Major => write!(f, "major"),
+            Bump::Minor => write!(f, "minor"),
+            Bump::Patch => write!(f, "patch"),
+        }
+    }
+}
+
+/// Version determined for a deployment
+#[derive(Debug, Clone)]
+pub struct ReleaseVersion {
+    /// The version being deployed
+    pub version: Version,
+    /// Tag for the version, i.e. the prefix followed by the version
+    pub tag: String,
+    /// Latest version tag before this deployment, if any
+    pub previous_tag: Option<String>,
+    /// How the version was derived from the previous one, `None` if unchanged
+    pub bump: Option<Bump>,
+}
+
+impl ReleaseVersion {
+    /// Returns whether the version's tag already exists
+    pub fn is_tagged(&self) -> bool {
+        self.previous_tag.as_deref() == Some(self.tag.as_str())
+    }
+}
+
+/// Determines the version of the commit checked out in a repository
+///
+/// The latest `<prefix>X.Y.Z` tag reachable from the commit is bumped by
+/// `bump`, or by what the commits since that tag call for. Without new
+/// commits or an explicit bump, the tagged version is kept. Without any
+/// version tag, the configured initial version is used.
+///
+/// # Arguments
+/// * `repo_path` - Checkout being deployed
+/// * `config` - Version settings of the release
+/// * `bump` - Bump requested on the command line, if any
+pub fn determine(
+    repo_path: &Path,
+    config: &VersionConfig,
+    bump: Option<Bump>,
+) -> Result<ReleaseVersion, DeployError> {
+    let previous = latest_tag(repo_path, &config.tag_prefix)?;
+    let commits = git::commits_between(
+        repo_path,
+        previous.as_ref().map(|(tag, _)| tag.as_str()),
+        "HEAD",
+    )?;
+
+    let (version, bump) = match &previous {
+        Some((_, base)) => {
+            let bump = bump.or_else(|| Bump::infer(&commits));
+            (bump.map_or(*base, |bump| base.bump(bump)), bump)
+        }
+        None => {
+            let initial = config.initial.parse::<Version>().map_err(|e| {
+                DeployError::ValidationError(format!("Invalid initial version: {}", e))
+            })?;
+            (initial, None)
+        }
+    };
+
+    Ok(ReleaseVersion {
+        version,
+        tag: format!("{}{}", config.tag_prefix, version),
+        previous_tag: previous.map(|(tag, _)| tag),
+        bump,
+    })
+}
+
+/// Returns the highest version tag reachable from `HEAD`, with its version
+fn latest_tag(repo_path: &Path, prefix: &str) -> Result<Option<(String, Version)>, DeployError> {
+    let tags = git::merged_tags(repo_path, &format!("{}*", prefix))?;
+    Ok(highest_version(tags, prefix))
+}
+
+/// Returns the tag with the highest version, ignoring tags that aren't `<prefix>X.Y.Z`
+fn highest_version(tags: Vec<String>, prefix: &str) -> Option<(String, Version)> {
+    tags.into_iter()
+        .filter_map(|tag| {
+            let version = tag.strip_prefix(prefix)?.parse::<Version>().ok()?;
+            Some((tag, version))
+        })
+        .max_by_key(|(_, version)| *version)
+}
+
+/// Replaces the `${version}` placeholder in a text
+///
+/// `$${` escapes a placeholder; any other placeholder is an error.
+pub fn substitute(text: &str, version: &Version) -> Result<String, DeployError> {
+    let values = BTreeMap::from([("version", version.to_string())]);
+    template::expand(text, &values).map_err(DeployError::ValidationError)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn version(major: u64, minor: u64, patch: u64) -> Version {
+        Version {
+            major,
+            minor,
+            patch,
+        }
+    }
+
+    fn commits(subjects: &[&str]) -> Vec<CommitInfo> {
+        subjects
+            .iter()
+            .map(|subject| CommitInfo {
+                sha: "0123456789abcdef".to_string(),
+                author: "Test".to_string(),
+                subject: subject.to_string(),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parses_versions() {
+        assert_eq!("1.2.3".parse::<Version>(), Ok(version(1, 2, 3)));
+        assert_eq!("0.10.0".parse::<Version>(), Ok(version(0, 10, 0)));
+        for invalid in ["1.2", "1.2.3.4", "1.2.3-rc1", "v1.2.3", "1.x.3", ""] {
+            assert!(invalid.parse::<Version>().is_err(), "{}", invalid);
+        }
+    }
+
+    #[test]
+    fn compares_versions_numerically() {
+        assert!(version(1, 10, 0) > version(1, 9, 9));
+        assert!(version(2, 0, 0) > version(1, 99, 99));
+        assert!(version(1, 2, 4) > version(1, 2, 3));
+    }
+
+    #[test]
+    fn bumps_reset_lower_components() {
+        let base = version(1, 2, 3);
+        assert_eq!(base.bump(Bump::Major), version(2, 0, 0));
+        assert_eq!(base.bump(Bump::Minor), version(1, 3, 0));
+        assert_eq!(base.bump(Bump::Patch), version(1, 2, 4));
+    }
+
+    #[test]
+    fn orders_bumps_by_size() {
+        assert!(Bump::Major > Bump::Minor);
+        assert!(Bump::Minor > Bump::Patch);
+    }
+
+    #[test]
+    fn infers_the_largest_bump() {
+        assert_eq!(Bump::infer(&[]), None);
+        assert_eq!(
+            Bump::infer(&commits(&["fix: typo", "Update README"])),
+            Some(Bump::Patch)
+        );
+        assert_eq!(
+            Bump::infer(&commits(&["fix: typo", "feat(api): add paging"])),
+            Some(Bump::Minor)
+        );
+        assert_eq!(
+            Bump::infer(&commits(&["feat: add paging", "fix!: drop old column"])),
+            Some(Bump::Major)
+        );
+        assert_eq!(
+            Bump::infer(&commits(&["BREAKING CHANGE: keys renamed"])),
+            Some(Bump::Major)
+        );
+    }
+
+    #[test]
+    fn picks_the_highest_tag_with_the_prefix() {
+        let tags = [
+            "v1.2",
+            "v1.2.3-rc1",
+            "v1.2.3",
+            "v1.10.0",
+            "v1.9.0",
+            "release-2.0.0",
+        ]
+        .map(String::from)
+        .to_vec();
+
+        assert_eq!(
+            highest_version(tags.clone(), "v"),
+            Some(("v1.10.0".to_string(), version(1, 10, 0)))
+        );
+        assert_eq!(
+            highest_version(tags, "release-"),
+            Some(("release-2.0.0".to_string(), version(2, 0, 0)))
+        );
+    }
+
+    #[test]
+    fn ignores_tags_that_arent_full_versions() {
+        let tags = ["v1.2", "v1.2.3-rc1"].map(String::from).to_vec();
+        assert_eq!(highest_version(tags, "v"), None);
+    }
+
+    #[test]
+    fn substitutes_the_version_placeholder() {
+        let version = version(1, 4, 0);
+        assert_eq!(
+            substitute("--version=${version}", &version).unwrap(),
+            "--version=1.4.0"
+        );
+        assert_eq!(substitute("$${version}", &version).unwrap(), "${version}");
+        assert!(substitute("${branch}", &version).is_err());
+    }
+}
@@ -0,0 +1,299 @@
+//! End-to-end tests running the `ud` binary in temporary directories
+
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// A temporary directory, removed when dropped
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> Self {
+        let path = std::env::temp_dir().join(format!("ud-test-{}", uuid::Uuid::new_v4()));
+        fs::create_dir_all(&path).unwrap();
+        TempDir(path)
+    }
+
+    fn path(&self) -> &Path {
+        &self.0
+    }
+
+    /// Writes a file relative to the directory, creating its parents
+    fn write(&self, name: &str, contents: &str) -> PathBuf {
+        let path = self.0.join(name);
+        fs::create_dir_all(path.parent().unwrap()).unwrap();
+        fs::write(&path, contents).unwrap();
+        path
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// Runs `ud` in a directory
+fn ud(dir: &Path, args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_ud"))
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .output()
+        .unwrap()
+}
+
+/// Runs git in a directory with a fixed identity, panicking if it fails
+fn git(dir: &Path, args: &[&str]) {
+    let output = Command::new("git")
+        .args(args)
+        .current_dir(dir)
+        .env("GIT_CONFIG_NOSYSTEM", "1")
+        .env("GIT_AUTHOR_NAME", "Test")
+        .env("GIT_AUTHOR_EMAIL", "test@example.com")
+        .env("GIT_COMMITTER_NAME", "Test")
+        .env("GIT_COMMITTER_EMAIL", "test@example.com")
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "git {:?}: {}",
+        args,
+        describe(&output)
+    );
+}
+
+/// Creates a repository with one commit on `main` in `<dir>/origin`, returning its path
+fn origin_repository(dir: &TempDir) -> String {
+    let origin = dir.write("origin/app.env", "ORIGINAL=1\n");
+    let origin = origin.parent().unwrap();
+    git(origin, &["init", "--quiet", "--initial-branch=main"]);
+    git(origin, &["add", "."]);
+    git(
+        origin,
+        &["commit", "--quiet", "--message", "Initial commit"],
+    );
+    origin.display().to_string()
+}
+
+/// Returns the exit code and both output streams, for assertion messages
+fn describe(output: &Output) -> String {
+    format!(
+        "exit code {:?}\nstdout:\n{}\nstderr:\n{}",
+        output.status.code(),
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+const POLICY_CONFIG: &str = "\
+release:
+  repository: https://example.com/app.git
+  branch: main
+  tool: \"true\"
+  allowed_branches: [main]
+";
+
+#[test]
+fn set_cannot_loosen_policies() {
+    let dir = TempDir::new();
+    dir.write("deploy.yml", POLICY_CONFIG);
+
+    let output = ud(
+        dir.path(),
+        &[
+            "validate",
+            "deploy.yml",
+            "--set",
+            "allowed_branches=[\"**\"]",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(11), "{}", describe(&output));
+    assert!(String::from_utf8_lossy(&output.stderr).contains("--override-policy"));
+
+    let output = ud(
+        dir.path(),
+        &[
+            "deploy.yml",
+            "--dry-run",
+            "--set",
+            "release.allowed_branches[0]=**",
+        ],
+    );
+    assert_eq!(output.status.code(), Some(11), "{}", describe(&output));
+
+    let output = ud(
+        dir.path(),
+        &[
+            "validate",
+            "deploy.yml",
+            "--set",
+            "allowed_branches=[\"**\"]",
+            "--override-policy",
+        ],
+    );
+    assert!(output.status.success(), "{}", describe(&output));
+}
+
+#[test]
+fn first_deploy_clones_into_empty_directory() {
+    let dir = TempDir::new();
+    let origin = origin_repository(&dir);
+    dir.write(
+        "deploy.yml",
+        &format!(
+            "release:\n  repository: {}\n  branch: main\n  clean: false\n  tool: \"true\"\n",
+            origin
+        ),
+    );
+    fs::create_dir(dir.path().join("app")).unwrap();
+
+    let output = ud(&dir.path().join("app"), &["../deploy.yml"]);
+
+    assert!(output.status.success(), "{}", describe(&output));
+    assert!(dir.path().join("app/.git").exists());
+    assert!(dir.path().join("app/app.env").exists());
+    assert!(!dir.path().join(".app.ud-lock").exists());
+}
+
+#[test]
+fn failed_resource_copy_restores_earlier_resources() {
+    let dir = TempDir::new();
+    let origin = origin_repository(&dir);
+    dir.write("resources/app.env", "SECRET=1\n");
+    dir.write(
+        "deploy.yml",
+        &format!(
+            "release:\n  repository: {}\n  branch: main\n  clean: false\n  tool: \"true\"\n  resources_cleanup: restore\n  resources:\n    - file: app.env\n      overwrite: always\n    - file: missing.env\n",
+            origin
+        ),
+    );
+    let app = dir.path().join("app");
+    fs::create_dir(&app).unwrap();
+
+    let output = ud(&app, &["../deploy.yml"]);
+
+    assert_eq!(output.status.code(), Some(30), "{}", describe(&output));
+    assert_eq!(
+        fs::read_to_string(app.join("app.env")).unwrap(),
+        "ORIGINAL=1\n"
+    );
+    assert!(!app.join(".ud/backup").exists());
+    assert!(!app.join(".ud/manifest.yml").exists());
+}
+
+#[test]
+fn version_placeholder_failure_removes_clean_checkout() {
+    let dir = TempDir::new();
+    let origin = origin_repository(&dir);
+    dir.write(
+        "deploy.yml",
+        &format!(
+            "release:\n  repository: {}\n  branch: main\n  clean: true\n  tool: \"true\"\n  version: {{}}\n  resources:\n    - file: app.env\n      copy: app-${{nope}}.env\n",
+            origin
+        ),
+    );
+    dir.write("resources/app.env", "SECRET=1\n");
+    let work = dir.path().join("work");
+    fs::create_dir(&work).unwrap();
+
+    let output = ud(&work, &["../deploy.yml"]);
+
+    assert_eq!(output.status.code(), Some(11), "{}", describe(&output));
+    let leftovers: Vec<_> = fs::read_dir(&work)
+        .unwrap()
+        .map(|entry| entry.unwrap().file_name())
+        .filter(|name| name != ".ud")
+        .collect();
+    assert!(leftovers.is_empty(), "left behind: {:?}", leftovers);
+}
+
+#[test]
+fn failure_after_checkout_is_recorded() {
+    let dir = TempDir::new();
+    let origin = origin_repository(&dir);
+    git(Path::new(&origin), &["branch", "v1.0.0"]);
+    dir.write(
+        "deploy.yml",
+        &format!(
+            "release:\n  repository: {}\n  branch: v1.0.0\n  clean: true\n  tool: \"true\"\n  require_tag_pattern: \"v*\"\n",
+            origin
+        ),
+    );
+    let work = dir.path().join("work");
+    fs::create_dir(&work).unwrap();
+
+    let output = ud(&work, &["../deploy.yml"]);
+
+    assert_eq!(output.status.code(), Some(11), "{}", describe(&output));
+    let history = fs::read_to_string(work.join(".ud/history.yml")).unwrap();
+    assert!(history.contains("succeeded: false"), "{}", history);
+}
+
+#[test]
+fn lockfile_tells_apart_resources_with_the_same_name() {
+    let dir = TempDir::new();
+    dir.write("staging/config.env", "ENV=staging\n");
+    dir.write("production/config.env", "ENV=production\n");
+    dir.write(
+        "deploy.yml",
+        "releases:\n  staging:\n    repository: https://example.com/app.git\n    branch: main\n    resources_dir: staging\n    resources:\n      - file: config.env\n  production:\n    repository: https://example.com/app.git\n    branch: main\n    resources_dir: production\n    resources:\n      - file: config.env\n",
+    );
+
+    let output = ud(dir.path(), &["resources", "lock", "deploy.yml"]);
+    assert!(output.status.success(), "{}", describe(&output));
+    let lockfile = fs::read_to_string(dir.path().join("deploy.lock")).unwrap();
+    assert!(lockfile.contains("staging/config.env"), "{}", lockfile);
+    assert!(lockfile.contains("production/config.env"), "{}", lockfile);
+
+    let output = ud(dir.path(), &["validate", "deploy.yml"]);
+    assert!(output.status.success(), "{}", describe(&output));
+
+    dir.write("production/config.env", "ENV=changed\n");
+    let output = ud(dir.path(), &["validate", "deploy.yml"]);
+    assert_eq!(output.status.code(), Some(11), "{}", describe(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("production/config.env"));
+}
+
+#[test]
+fn remote_check_accepts_tags() {
+    let dir = TempDir::new();
+    let origin = origin_repository(&dir);
+    git(Path::new(&origin), &["tag", "v1.0.0"]);
+    let config = |branch: &str| {
+        format!(
+            "release:\n  repository: {}\n  branch: {}\n  require_tag_pattern: \"v*\"\n",
+            origin, branch
+        )
+    };
+
+    dir.write("deploy.yml", &config("v1.0.0"));
+    let output = ud(dir.path(), &["validate", "deploy.yml", "--remote"]);
+    assert!(output.status.success(), "{}", describe(&output));
+
+    dir.write("deploy.yml", &config("v2.0.0"));
+    let output = ud(dir.path(), &["validate", "deploy.yml", "--remote"]);
+    assert_eq!(output.status.code(), Some(11), "{}", describe(&output));
+    assert!(String::from_utf8_lossy(&output.stdout).contains("No branch or tag 'v2.0.0'"));
+}
+
+#[test]
+fn malformed_lockfile_is_one_more_lint_problem() {
+    let dir = TempDir::new();
+    dir.write("deploy.lock", "resources: [unclosed\n");
+    dir.write(
+        "deploy.yml",
+        "releases:\n  staging:\n    repository: https://example.com/app.git\n    branch: main\n    resources:\n      - file: missing.env\n  production:\n    repository: https://example.com/app.git\n    branch: main\n",
+    );
+
+    let output = ud(dir.path(), &["validate", "deploy.yml"]);
+    assert_eq!(output.status.code(), Some(11), "{}", describe(&output));
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(
+        stdout.matches("Failed to parse lockfile").count(),
+        1,
+        "{}",
+        stdout
+    );
+    assert!(stdout.contains("missing.env"), "{}", stdout);
+}